@@ -0,0 +1,131 @@
+//! Compares the number of search states the routers settle on the same
+//! queries over a synthetic street grid, after building contraction
+//! hierarchies of it. Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use geo::{point, HaversineDistance};
+use osmpbfreader::{Node, NodeId, Tags};
+use read_osm::{
+    graph::{AdjList, Edge, Nodes},
+    AStar, BidirectionalDijkstra, ContractionHierarchy, CustomizableContractionHierarchy, Dijkstra,
+    Profile, RoadGraph, Router,
+};
+
+const GRID_SIZE: i64 = 200;
+const QUERIES: usize = 100;
+
+/// A grid of streets 100 m apart whose speeds vary between blocks.
+fn grid_graph(profile: &Profile) -> RoadGraph {
+    let id = |row: i64, col: i64| NodeId(row * GRID_SIZE + col);
+    let nodes: Nodes = (0..GRID_SIZE)
+        .flat_map(|row| (0..GRID_SIZE).map(move |col| (row, col)))
+        .map(|(row, col)| {
+            let node = Node {
+                id: id(row, col),
+                tags: Tags::new(),
+                decimicro_lat: 350_000_000 + row as i32 * 9_000,
+                decimicro_lon: 1_390_000_000 + col as i32 * 11_000,
+            };
+            (node.id, node)
+        })
+        .collect();
+
+    let speeds = [12.0, 15.0, 15.0, 18.0, 20.0];
+    let mut adj_list = AdjList::new();
+    for row in 0..GRID_SIZE {
+        for col in 0..GRID_SIZE {
+            for (next_row, next_col) in [(row + 1, col), (row, col + 1)] {
+                if next_row == GRID_SIZE || next_col == GRID_SIZE {
+                    continue;
+                }
+                let (u, v) = (id(row, col), id(next_row, next_col));
+                let length = point!(x: nodes[&u].lon(), y: nodes[&u].lat())
+                    .haversine_distance(&point!(x: nodes[&v].lon(), y: nodes[&v].lat()));
+                let speed = speeds[((row * 7 + col * 13) % speeds.len() as i64) as usize];
+                let cost = length / (speed / 3.6);
+                for (from, to) in [(u, v), (v, u)] {
+                    adj_list.entry(from).or_default().push(Edge {
+                        target: to,
+                        length,
+                        cost,
+                        major: false,
+                    });
+                }
+            }
+        }
+    }
+    RoadGraph::new(nodes, adj_list).with_min_cost_per_meter(profile.min_cost_per_meter())
+}
+
+/// Deterministic pseudo random node pairs.
+fn queries() -> Vec<(NodeId, NodeId)> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        NodeId((state % (GRID_SIZE * GRID_SIZE) as u64) as i64)
+    };
+    (0..QUERIES).map(|_| (next(), next())).collect()
+}
+
+fn main() {
+    let profile = Profile::parse(
+        "name = \"bench\"\noneway = \"respect\"\n[speed]\ndefault = 20\n",
+        "bench",
+    )
+    .unwrap();
+    let graph = grid_graph(&profile);
+    let queries = queries();
+    let ch = ContractionHierarchy::build(&graph);
+    let cch = CustomizableContractionHierarchy::build(&graph)
+        .customize(&graph)
+        .unwrap();
+
+    let routers: [(&str, &dyn Router); 5] = [
+        ("dijkstra", &Dijkstra),
+        ("astar", &AStar),
+        ("bidir", &BidirectionalDijkstra),
+        ("ch", &ch),
+        ("cch", &cch),
+    ];
+    let mut costs = Vec::new();
+    for (name, router) in routers {
+        let mut settled = 0;
+        let mut elapsed = Duration::ZERO;
+        let mut router_costs = Vec::new();
+        for &(start, goal) in &queries {
+            let timer = Instant::now();
+            let route = router
+                .route(&graph, start, goal)
+                .expect("the grid is connected");
+            elapsed += timer.elapsed();
+            settled += route.settled;
+            router_costs.push((route.cost, route.nodes.len()));
+        }
+        println!(
+            "{:<8} {:>10} settled states, {:>8.1} per query, {:.3} s",
+            name,
+            settled,
+            settled as f64 / QUERIES as f64,
+            elapsed.as_secs_f64()
+        );
+        costs.push(router_costs);
+    }
+
+    // Routers sum costs in thousandths, so equally good routes may differ by
+    // the rounding of their edges.
+    for (name, router_costs) in routers.iter().map(|(name, _)| name).zip(&costs).skip(1) {
+        for (i, (&(dijkstra, len), &(cost, _))) in costs[0].iter().zip(router_costs).enumerate() {
+            assert!(
+                (dijkstra - cost).abs() <= 0.0005 * len as f64,
+                "query {} costs {} with dijkstra but {} with {}",
+                i,
+                dijkstra,
+                cost,
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,468 @@
+//! Binary on-disk format of the road graph.
+//!
+//! All integers are little-endian. The header is padded to a multiple of 8 bytes
+//! and the arrays are ordered by descending alignment, so every array starts at
+//! an offset aligned to its element size.
+//!
+//! | field         | type                          |
+//! |---------------|-------------------------------|
+//! | magic         | `b"ROSMGRPH"`                 |
+//! | version       | `u32`                         |
+//! | PBF size      | `u64`                         |
+//! | PBF mtime     | `i64`                         |
+//! | PBF timestamp | `i64`                         |
+//! | profile hash  | `u64`                         |
+//! | replication   | `i64`                         |
+//! | revision      | `u64`                         |
+//! | source PBF    | `u32` length + UTF-8          |
+//! | profile       | `u32` length + UTF-8          |
+//! | DEM           | `u32` length + UTF-8          |
+//! | padding       | zeros up to 8 byte alignment  |
+//! | node count    | `u64`                         |
+//! | edge count    | `u64`                         |
+//! | restr. count  | `u64`                         |
+//! | restr. length | `u64`                         |
+//! | via count     | `u64`                         |
+//! | node IDs      | `[i64; node count]`           |
+//! | coordinates   | `[(i32, i32); node count]`    |
+//! | elevations    | `[f32; node count]`           |
+//! | edge offsets  | `[u32; node count + 1]`       |
+//! | edge targets  | `[u32; edge count]`           |
+//! | edge lengths  | `[f32; edge count]`           |
+//! | edge costs    | `[f32; edge count]`           |
+//! | via starts    | `[u32; edge count + 1]`       |
+//! | via nodes     | `[u32; via count]`            |
+//! | via lengths   | `[f32; via count]`            |
+//! | via costs     | `[f32; via count]`            |
+//! | restr. starts | `[u32; restr. count + 1]`     |
+//! | restr. nodes  | `[u32; restr. length]`        |
+//! | restr. kinds  | `[u8; restr. count]`          |
+//! | edge flags    | `[u8; edge count]`            |
+//!
+//! The PBF fields are a [`PbfFingerprint`] of the source extract, and DEM is
+//! the directory of the elevation tiles with a hash of their names, sizes and
+//! modification times. Replication is the sequence number of the OSM data the
+//! graph reflects, that of the PBF until [diffs](crate::osc) are applied, or 0
+//! if unknown. Revision counts the times diffs were applied since the graph was
+//! built from the PBF.
+//!
+//! Coordinates are `(lat, lon)` in decimicro degrees, and elevations are in
+//! meters or NaN if unknown. The outgoing edges of the
+//! `i`-th node are `offsets[i]..offsets[i + 1]`, and targets are node indices.
+//! Turn restrictions are stored the same way as node index sequences, with
+//! kind `0` for `no_*` and `1` for `only_*`. Edge flags are a bit set of
+//! [`MAJOR_EDGE`]. The nodes an edge collapsing a chain passes through are
+//! stored the same way by edge, with the length and cost up to each of them.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{error::Error, fingerprint::PbfFingerprint, restriction::RestrictionKind};
+
+const MAGIC: &[u8; 8] = b"ROSMGRPH";
+pub const FORMAT_VERSION: u32 = 9;
+/// Edge flag of edges on a major road.
+pub const MAJOR_EDGE: u8 = 1;
+// magic + version + PBF fingerprint + profile hash + replication + revision
+const FIXED_HEADER_LEN: usize = 8 + 4 + 8 * 6;
+
+/// Where a cached graph came from, used to detect stale caches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheHeader {
+    pub version: u32,
+    pub source_pbf: String,
+    pub pbf: PbfFingerprint,
+    pub profile: String,
+    pub profile_hash: u64,
+    /// Directory of the elevation tiles with a hash of them, or empty if
+    /// built without them.
+    pub dem: String,
+    /// Replication sequence number of the OSM data, or 0 if unknown.
+    pub replication_sequence: i64,
+    /// Number of times diffs were applied since the graph was built from the
+    /// PBF, so that hierarchies of earlier revisions don't match.
+    pub revision: u64,
+}
+
+/// The graph in compressed sparse row form.
+#[derive(Debug, Default, PartialEq)]
+pub struct CsrGraph {
+    pub node_ids: Vec<i64>,
+    pub coords: Vec<(i32, i32)>,
+    pub elevations: Vec<f32>,
+    pub offsets: Vec<u32>,
+    pub targets: Vec<u32>,
+    pub lengths: Vec<f32>,
+    pub costs: Vec<f32>,
+    pub via_offsets: Vec<u32>,
+    pub via_nodes: Vec<u32>,
+    pub via_lengths: Vec<f32>,
+    pub via_costs: Vec<f32>,
+    pub restriction_offsets: Vec<u32>,
+    pub restriction_nodes: Vec<u32>,
+    pub restriction_kinds: Vec<RestrictionKind>,
+    pub edge_flags: Vec<u8>,
+}
+
+impl CacheHeader {
+    /// Reads only the header of the cache at `path`.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        read_header(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Writes `header` padded to a multiple of 8 bytes.
+pub(crate) fn write_header(w: &mut impl Write, header: &CacheHeader) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&header.version.to_le_bytes())?;
+    w.write_all(&header.pbf.size.to_le_bytes())?;
+    w.write_all(&header.pbf.modified.to_le_bytes())?;
+    w.write_all(&header.pbf.replication_timestamp.to_le_bytes())?;
+    w.write_all(&header.profile_hash.to_le_bytes())?;
+    w.write_all(&header.replication_sequence.to_le_bytes())?;
+    w.write_all(&header.revision.to_le_bytes())?;
+    let mut header_len = FIXED_HEADER_LEN;
+    for s in [&header.source_pbf, &header.profile, &header.dem] {
+        write_string(w, s)?;
+        header_len += 4 + s.len();
+    }
+    w.write_all(&vec![0; (8 - header_len % 8) % 8])
+}
+
+/// Writes `s` as a `u32` length and UTF-8.
+pub(crate) fn write_string(w: &mut impl Write, s: &str) -> io::Result<()> {
+    w.write_all(&(s.len() as u32).to_le_bytes())?;
+    w.write_all(s.as_bytes())
+}
+
+pub fn write(path: &Path, header: &CacheHeader, graph: &CsrGraph) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_header(&mut w, header)?;
+
+    w.write_all(&(graph.node_ids.len() as u64).to_le_bytes())?;
+    w.write_all(&(graph.targets.len() as u64).to_le_bytes())?;
+    w.write_all(&(graph.restriction_kinds.len() as u64).to_le_bytes())?;
+    w.write_all(&(graph.restriction_nodes.len() as u64).to_le_bytes())?;
+    w.write_all(&(graph.via_nodes.len() as u64).to_le_bytes())?;
+    for id in &graph.node_ids {
+        w.write_all(&id.to_le_bytes())?;
+    }
+    for (lat, lon) in &graph.coords {
+        w.write_all(&lat.to_le_bytes())?;
+        w.write_all(&lon.to_le_bytes())?;
+    }
+    for elevation in &graph.elevations {
+        w.write_all(&elevation.to_le_bytes())?;
+    }
+    for offset in &graph.offsets {
+        w.write_all(&offset.to_le_bytes())?;
+    }
+    for target in &graph.targets {
+        w.write_all(&target.to_le_bytes())?;
+    }
+    for len in &graph.lengths {
+        w.write_all(&len.to_le_bytes())?;
+    }
+    for cost in &graph.costs {
+        w.write_all(&cost.to_le_bytes())?;
+    }
+    for offset in &graph.via_offsets {
+        w.write_all(&offset.to_le_bytes())?;
+    }
+    for node in &graph.via_nodes {
+        w.write_all(&node.to_le_bytes())?;
+    }
+    for len in &graph.via_lengths {
+        w.write_all(&len.to_le_bytes())?;
+    }
+    for cost in &graph.via_costs {
+        w.write_all(&cost.to_le_bytes())?;
+    }
+    for offset in &graph.restriction_offsets {
+        w.write_all(&offset.to_le_bytes())?;
+    }
+    for node in &graph.restriction_nodes {
+        w.write_all(&node.to_le_bytes())?;
+    }
+    for kind in &graph.restriction_kinds {
+        w.write_all(&[match kind {
+            RestrictionKind::No => 0,
+            RestrictionKind::Only => 1,
+        }])?;
+    }
+    w.write_all(&graph.edge_flags)?;
+    w.flush()
+}
+
+pub fn read(path: &Path) -> io::Result<(CacheHeader, CsrGraph)> {
+    let mut r = BufReader::new(File::open(path)?);
+    let header = read_header(&mut r)?;
+
+    let node_count = read_u64(&mut r)? as usize;
+    let edge_count = read_u64(&mut r)? as usize;
+    let restriction_count = read_u64(&mut r)? as usize;
+    let restriction_node_count = read_u64(&mut r)? as usize;
+    let via_count = read_u64(&mut r)? as usize;
+    let node_ids = read_array(&mut r, node_count, i64::from_le_bytes)?;
+    let coords = read_array(&mut r, node_count, |b: [u8; 8]| {
+        (
+            i32::from_le_bytes(b[..4].try_into().unwrap()),
+            i32::from_le_bytes(b[4..].try_into().unwrap()),
+        )
+    })?;
+    let elevations = read_array(&mut r, node_count, f32::from_le_bytes)?;
+    let offsets = read_array(&mut r, offsets_len(node_count)?, u32::from_le_bytes)?;
+    let targets = read_array(&mut r, edge_count, u32::from_le_bytes)?;
+    let lengths = read_array(&mut r, edge_count, f32::from_le_bytes)?;
+    let costs = read_array(&mut r, edge_count, f32::from_le_bytes)?;
+    let via_offsets = read_array(&mut r, offsets_len(edge_count)?, u32::from_le_bytes)?;
+    let via_nodes = read_array(&mut r, via_count, u32::from_le_bytes)?;
+    let via_lengths = read_array(&mut r, via_count, f32::from_le_bytes)?;
+    let via_costs = read_array(&mut r, via_count, f32::from_le_bytes)?;
+    let restriction_offsets =
+        read_array(&mut r, offsets_len(restriction_count)?, u32::from_le_bytes)?;
+    let restriction_nodes = read_array(&mut r, restriction_node_count, u32::from_le_bytes)?;
+    let restriction_kinds = read_array(&mut r, restriction_count, |[b]: [u8; 1]| match b {
+        0 => Some(RestrictionKind::No),
+        1 => Some(RestrictionKind::Only),
+        _ => None,
+    })?
+    .into_iter()
+    .collect::<Option<Vec<_>>>()
+    .ok_or_else(|| invalid_data("unknown restriction kind".to_string()))?;
+    let edge_flags = read_array(&mut r, edge_count, |[b]: [u8; 1]| b)?;
+
+    if offsets.last().copied() != Some(edge_count as u32)
+        || offsets.windows(2).any(|w| w[0] > w[1])
+        || targets.iter().any(|&t| t as usize >= node_count)
+    {
+        return Err(invalid_data("edge arrays are inconsistent".to_string()));
+    }
+    if via_offsets.last().copied() != Some(via_count as u32)
+        || via_offsets.windows(2).any(|w| w[0] > w[1])
+        || via_nodes.iter().any(|&n| n as usize >= node_count)
+    {
+        return Err(invalid_data("via arrays are inconsistent".to_string()));
+    }
+    if restriction_offsets.last().copied() != Some(restriction_node_count as u32)
+        || restriction_offsets.windows(2).any(|w| w[0] > w[1])
+        || restriction_nodes.iter().any(|&n| n as usize >= node_count)
+    {
+        return Err(invalid_data(
+            "restriction arrays are inconsistent".to_string(),
+        ));
+    }
+
+    Ok((
+        header,
+        CsrGraph {
+            node_ids,
+            coords,
+            elevations,
+            offsets,
+            targets,
+            lengths,
+            costs,
+            via_offsets,
+            via_nodes,
+            via_lengths,
+            via_costs,
+            restriction_offsets,
+            restriction_nodes,
+            restriction_kinds,
+            edge_flags,
+        },
+    ))
+}
+
+pub(crate) fn read_header(r: &mut impl Read) -> io::Result<CacheHeader> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a graph cache file".to_string()));
+    }
+    let version = u32::from_le_bytes(read_bytes(r)?);
+    if version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported cache format version {} (expected {})",
+            version, FORMAT_VERSION
+        )));
+    }
+    let pbf = PbfFingerprint {
+        size: read_u64(r)?,
+        modified: i64::from_le_bytes(read_bytes(r)?),
+        replication_timestamp: i64::from_le_bytes(read_bytes(r)?),
+    };
+    let profile_hash = read_u64(r)?;
+    let replication_sequence = i64::from_le_bytes(read_bytes(r)?);
+    let revision = read_u64(r)?;
+    let source_pbf = read_string(r)?;
+    let profile = read_string(r)?;
+    let dem = read_string(r)?;
+    let header_len = FIXED_HEADER_LEN + 12 + source_pbf.len() + profile.len() + dem.len();
+    let mut padding = [0; 8];
+    r.read_exact(&mut padding[..(8 - header_len % 8) % 8])?;
+    Ok(CacheHeader {
+        version,
+        source_pbf,
+        pbf,
+        profile,
+        profile_hash,
+        dem,
+        replication_sequence,
+        revision,
+    })
+}
+
+pub(crate) fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let len = u32::from_le_bytes(read_bytes(r)?) as usize;
+    let buf = read_array(r, len, |[b]: [u8; 1]| b)?;
+    String::from_utf8(buf).map_err(|e| invalid_data(e.to_string()))
+}
+
+pub(crate) fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(r)?))
+}
+
+pub(crate) fn read_bytes<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Length of the offsets into an array of `count` items' elements.
+pub(crate) fn offsets_len(count: usize) -> io::Result<usize> {
+    count
+        .checked_add(1)
+        .ok_or_else(|| invalid_data(format!("count {} is too large", count)))
+}
+
+/// Reads `len` items of `N` bytes each. A corrupt `len` fails once the file
+/// runs out rather than allocating it all up front.
+pub(crate) fn read_array<const N: usize, T>(
+    r: &mut impl Read,
+    len: usize,
+    decode: impl Fn([u8; N]) -> T,
+) -> io::Result<Vec<T>> {
+    let size = len
+        .checked_mul(N)
+        .ok_or_else(|| invalid_data(format!("array length {} is too large", len)))?;
+    let mut buf = Vec::new();
+    r.take(size as u64).read_to_end(&mut buf)?;
+    if buf.len() != size {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf
+        .chunks_exact(N)
+        .map(|chunk| decode(chunk.try_into().unwrap()))
+        .collect())
+}
+
+/// Turns an error reading the cache file at `path` into [`Error::CacheCorrupt`]
+/// if the file is there but can't be used.
+pub(crate) fn read_error(path: &Path, err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Error::CacheCorrupt {
+            path: path.to_path_buf(),
+            reason: err.to_string(),
+        },
+        _ => Error::Io(err),
+    }
+}
+
+pub(crate) fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> CacheHeader {
+        CacheHeader {
+            version: FORMAT_VERSION,
+            source_pbf: "test.osm.pbf".to_string(),
+            pbf: PbfFingerprint {
+                size: 1234,
+                modified: 1_700_000_000,
+                replication_timestamp: 1_700_000_100,
+            },
+            profile: "bicycle".to_string(),
+            profile_hash: 0x0123_4567_89ab_cdef,
+            dem: "dem".to_string(),
+            replication_sequence: 4321,
+            revision: 2,
+        }
+    }
+
+    /// Nodes 0 -> 1 -> 2 with the second edge passing through 3, and a
+    /// restriction 0, 1, 2.
+    fn graph() -> CsrGraph {
+        CsrGraph {
+            node_ids: vec![10, 20, 30, 40],
+            coords: vec![(1, 2), (3, 4), (5, 6), (7, 8)],
+            elevations: vec![1.0, 2.0, 3.0, 4.0],
+            offsets: vec![0, 1, 2, 2, 2],
+            targets: vec![1, 2],
+            lengths: vec![10.0, 20.0],
+            costs: vec![15.0, 25.0],
+            via_offsets: vec![0, 0, 1],
+            via_nodes: vec![3],
+            via_lengths: vec![5.0],
+            via_costs: vec![7.5],
+            restriction_offsets: vec![0, 3],
+            restriction_nodes: vec![0, 1, 2],
+            restriction_kinds: vec![RestrictionKind::No],
+            edge_flags: vec![MAJOR_EDGE, 0],
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("read-osm-{}-{}.bin", name, std::process::id()))
+    }
+
+    #[test]
+    fn graphs_are_read_as_written() {
+        let path = temp_path("round-trip");
+        write(&path, &header(), &graph()).unwrap();
+        let result = read(&path);
+        let header_only = CacheHeader::read(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (read_header, read_graph) = result.unwrap();
+        assert_eq!(read_header, header());
+        assert_eq!(header_only.unwrap(), header());
+        assert_eq!(read_graph, graph());
+    }
+
+    #[test]
+    fn decreasing_offsets_are_rejected() {
+        let path = temp_path("offsets");
+        let mut graph = graph();
+        graph.offsets = vec![0, 2, 1, 2, 2];
+        write(&path, &header(), &graph).unwrap();
+        let result = read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn counts_past_the_end_of_the_file_are_rejected() {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, &header()).unwrap();
+        for count in [u64::MAX, 0, 0, 0, 0] {
+            bytes.extend(count.to_le_bytes());
+        }
+        let path = temp_path("counts");
+        std::fs::write(&path, bytes).unwrap();
+        let result = read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result.unwrap_err().kind(),
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
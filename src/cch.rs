@@ -0,0 +1,650 @@
+//! Customizable contraction hierarchies, whose preprocessing depends only on
+//! the topology of the graph so that new edge costs are applied in seconds.
+//!
+//! The states of [`Dijkstra`](crate::Dijkstra) are ordered by nested
+//! dissection and the graph of their arcs, taken as undirected, is completed
+//! so that contracting them in that order adds no further shortcuts.
+//! [`CustomizableContractionHierarchy::customize`] then computes the weights
+//! of all those arcs for a graph with the same topology, giving a
+//! [`ContractionHierarchy`] to route on.
+//!
+//! The topology is that of the graph after its chains are collapsed, which
+//! depends on more of the profile than costs. Customizing works for profiles
+//! that only change `[speed]`, `[factors]`, `climb_penalty` or the penalties
+//! of `[turns]`, and for graphs that only lack states and arcs of the
+//! topology. Changing the ways accepted, their directions, `major_highways`,
+//! the turn restrictions or whether there are `[turns]` can collapse other
+//! chains or change the states of edge based graphs, which fails with
+//! [`Error::TopologyMismatch`].
+//!
+//! It is saved next to the graph cache as `graph-<profile>.cch`:
+//!
+//! | field          | type                                     |
+//! |----------------|------------------------------------------|
+//! | magic          | `b"ROSMCCHT"`                            |
+//! | version        | `u32`                                    |
+//! | padding        | 4 zeros                                  |
+//! | graph header   | as in the [graph cache](crate::cache)    |
+//! | state count    | `u64`                                    |
+//! | arc count      | `u64`                                    |
+//! | state nodes    | `[i64; state count]`                     |
+//! | previous nodes | `[i64; state count]`, `i64::MIN` if none |
+//! | state turns    | `[u32; state count]`                     |
+//! | state ranks    | `[u32; state count]`                     |
+//! | arc offsets    | `[u32; state count + 1]`                 |
+//! | arc heads      | `[u32; arc count]`                       |
+//!
+//! The arcs of the `i`-th state are `offsets[i]..offsets[i + 1]`, leading to
+//! higher ranked states in ascending rank order.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use osmpbfreader::NodeId;
+
+use crate::{
+    cache::{self, CacheHeader},
+    ch::{state_graph, ChArc, ContractionHierarchy, NO_NODE},
+    error::Error,
+    graph::RoadGraph,
+    progress,
+    router::{successors, to_fixed, State},
+};
+
+const MAGIC: &[u8; 8] = b"ROSMCCHT";
+const FORMAT_VERSION: u32 = 2;
+/// Parts at most this large aren't dissected further.
+const LEAF_SIZE: usize = 4;
+const INFINITY: u64 = u64::MAX;
+
+/// A search [`State`] by OSM node IDs, which unlike indices are the same in
+/// every graph of the topology.
+type NodeState = (NodeId, u32, Option<NodeId>);
+
+/// The metric independent part of a customizable contraction hierarchy.
+pub struct CustomizableContractionHierarchy {
+    /// Header of the graph the topology was taken from.
+    header: CacheHeader,
+    states: Vec<NodeState>,
+    state_ids: HashMap<NodeState, u32>,
+    ranks: Vec<u32>,
+    offsets: Vec<u32>,
+    heads: Vec<u32>,
+}
+
+impl CustomizableContractionHierarchy {
+    /// Orders the states of `graph` and adds the shortcuts any costs may need.
+    pub fn build(graph: &RoadGraph) -> Self {
+        let timer = Instant::now();
+        let (states, arcs) = state_graph(graph);
+        let mut neighbors = vec![Vec::new(); states.len()];
+        for arc in arcs.iter().filter(|arc| arc.source != arc.target) {
+            neighbors[arc.source as usize].push(arc.target);
+            neighbors[arc.target as usize].push(arc.source);
+        }
+        for list in &mut neighbors {
+            list.sort_unstable();
+            list.dedup();
+        }
+
+        let mut ranks = vec![0; states.len()];
+        let order = nested_dissection(&neighbors);
+        for (rank, &state) in order.iter().enumerate() {
+            ranks[state as usize] = rank as u32;
+        }
+
+        // Contracting a state joins its higher ranked neighbors. It's enough to
+        // add them to the lowest of them, which passes them on when contracted.
+        let mut upper = neighbors
+            .into_iter()
+            .enumerate()
+            .map(|(state, list)| {
+                list.into_iter()
+                    .filter(|&other| ranks[other as usize] > ranks[state])
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for &state in &order {
+            let mut list = std::mem::take(&mut upper[state as usize]);
+            list.sort_unstable_by_key(|&other| ranks[other as usize]);
+            list.dedup();
+            if let Some((&lowest, rest)) = list.split_first() {
+                upper[lowest as usize].extend_from_slice(rest);
+            }
+            upper[state as usize] = list;
+        }
+
+        let mut offsets = vec![0];
+        let mut heads = Vec::new();
+        for list in upper {
+            heads.extend(list);
+            offsets.push(heads.len() as u32);
+        }
+        let states = states
+            .into_iter()
+            .map(|state| node_state(graph, state))
+            .collect();
+        let cch = Self::new(graph.header().clone(), states, ranks, offsets, heads);
+        progress::status(format_args!(
+            "Customizable contraction hierarchy built: {} states, {} arcs ({} s)",
+            cch.states.len(),
+            cch.heads.len(),
+            timer.elapsed().as_secs_f64()
+        ));
+        cch
+    }
+
+    fn new(
+        header: CacheHeader,
+        states: Vec<NodeState>,
+        ranks: Vec<u32>,
+        offsets: Vec<u32>,
+        heads: Vec<u32>,
+    ) -> Self {
+        let state_ids = states
+            .iter()
+            .enumerate()
+            .map(|(i, &state)| (state, i as u32))
+            .collect();
+        CustomizableContractionHierarchy {
+            header,
+            states,
+            state_ids,
+            ranks,
+            offsets,
+            heads,
+        }
+    }
+
+    /// Computes the arc weights for the costs of `graph`, which must not have
+    /// search states or transitions the topology lacks.
+    pub fn customize(&self, graph: &RoadGraph) -> Result<ContractionHierarchy, Error> {
+        let timer = Instant::now();
+        let mismatch = || Error::TopologyMismatch {
+            pbf: self.header.source_pbf.clone(),
+            profile: self.header.profile.clone(),
+        };
+
+        // `up[i]` is the weight from the tail of the `i`-th arc to its head,
+        // `down[i]` the other way round, with the state they go via if they
+        // are shortcuts.
+        let mut up = vec![(INFINITY, None); self.heads.len()];
+        let mut down = vec![(INFINITY, None); self.heads.len()];
+        // The states routes start from and the transitions between known
+        // states cover all states of `graph`.
+        let known = |state| self.state_ids.contains_key(&node_state(graph, state));
+        for source in graph.sources() {
+            let known = if graph.is_edge_based() {
+                successors(graph, (source, 0, None)).all(|(next, _, _)| known(next))
+            } else {
+                known((source, 0, None))
+            };
+            if !known {
+                return Err(mismatch());
+            }
+        }
+        // States of nodes the graph lacks have no transitions and are never
+        // reached.
+        let index = |id| graph.index_of(id).unwrap_or(NO_NODE);
+        let states = self
+            .states
+            .iter()
+            .map(|&(node, turn, prev)| (index(node), turn, prev.map(index)))
+            .collect::<Vec<State>>();
+        for (id, &state) in states.iter().enumerate() {
+            let (node, _, prev) = state;
+            if node == NO_NODE || prev == Some(NO_NODE) {
+                continue;
+            }
+            for (next, edge, turn_cost) in successors(graph, state) {
+                let next = *self
+                    .state_ids
+                    .get(&node_state(graph, next))
+                    .ok_or_else(mismatch)?;
+                if next == id as u32 {
+                    continue;
+                }
+                let (arc, forward) = self.arc_between(id as u32, next).ok_or_else(mismatch)?;
+                let (weight, _) = if forward {
+                    &mut up[arc]
+                } else {
+                    &mut down[arc]
+                };
+                *weight = (*weight).min(to_fixed(edge.cost + turn_cost));
+            }
+        }
+
+        // Lower triangles are done first, so the arcs of a state are final
+        // by the time it is the via state of others.
+        let mut order = (0..self.states.len() as u32).collect::<Vec<_>>();
+        order.sort_unstable_by_key(|&state| self.ranks[state as usize]);
+        for via in order {
+            let arcs = self.arcs(via);
+            for (i, lower) in arcs.clone().enumerate() {
+                for higher in arcs.clone().skip(i + 1) {
+                    let (from, to) = (self.heads[lower], self.heads[higher]);
+                    let (arc, _) = self.arc_between(from, to).unwrap();
+                    let there = down[lower].0.saturating_add(up[higher].0);
+                    if there < up[arc].0 {
+                        up[arc] = (there, Some(via));
+                    }
+                    let back = down[higher].0.saturating_add(up[lower].0);
+                    if back < down[arc].0 {
+                        down[arc] = (back, Some(via));
+                    }
+                }
+            }
+        }
+
+        // Arcs of the hierarchy are numbered in order, skipping those that
+        // can't be used.
+        let mut ids = vec![[u32::MAX; 2]; self.heads.len()];
+        let mut ch_arcs = Vec::new();
+        for tail in 0..self.states.len() as u32 {
+            for arc in self.arcs(tail) {
+                let head = self.heads[arc];
+                let directions = [(tail, head, up[arc]), (head, tail, down[arc])];
+                for (i, (source, target, (weight, _))) in directions.into_iter().enumerate() {
+                    if weight != INFINITY {
+                        ids[arc][i] = ch_arcs.len() as u32;
+                        ch_arcs.push(ChArc {
+                            source,
+                            target,
+                            weight,
+                            halves: None,
+                        });
+                    }
+                }
+            }
+        }
+        for tail in 0..self.states.len() as u32 {
+            for arc in self.arcs(tail) {
+                let head = self.heads[arc];
+                for (i, (_, via)) in [up[arc], down[arc]].into_iter().enumerate() {
+                    let Some(via) = via else {
+                        continue;
+                    };
+                    let (to_tail, _) = self.arc_between(via, tail).unwrap();
+                    let (to_head, _) = self.arc_between(via, head).unwrap();
+                    // Going up is tail, via, head and going down the reverse.
+                    ch_arcs[ids[arc][i] as usize].halves = Some(if i == 0 {
+                        (ids[to_tail][1], ids[to_head][0])
+                    } else {
+                        (ids[to_head][1], ids[to_tail][0])
+                    });
+                }
+            }
+        }
+
+        let ch =
+            ContractionHierarchy::new(graph.header().clone(), states, self.ranks.clone(), ch_arcs);
+        progress::status(format_args!(
+            "Hierarchy customized: {} arcs ({} s)",
+            ch.arc_count(),
+            timer.elapsed().as_secs_f64()
+        ));
+        Ok(ch)
+    }
+
+    /// Where the topology of the graph cached for `profile_name` is saved.
+    pub fn path(cache_dir: &Path, profile_name: &str) -> PathBuf {
+        cache_dir.join(format!("graph-{}.cch", profile_name))
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(cache_dir)?;
+        let path = Self::path(cache_dir, &self.header.profile);
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        w.write_all(&[0; 4])?;
+        cache::write_header(&mut w, &self.header)?;
+
+        w.write_all(&(self.states.len() as u64).to_le_bytes())?;
+        w.write_all(&(self.heads.len() as u64).to_le_bytes())?;
+        for (node, _, _) in &self.states {
+            w.write_all(&node.0.to_le_bytes())?;
+        }
+        for (_, _, prev) in &self.states {
+            w.write_all(&prev.map_or(i64::MIN, |prev| prev.0).to_le_bytes())?;
+        }
+        for (_, turn, _) in &self.states {
+            w.write_all(&turn.to_le_bytes())?;
+        }
+        for rank in &self.ranks {
+            w.write_all(&rank.to_le_bytes())?;
+        }
+        for offset in &self.offsets {
+            w.write_all(&offset.to_le_bytes())?;
+        }
+        for head in &self.heads {
+            w.write_all(&head.to_le_bytes())?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::read(path).map_err(|err| cache::read_error(path, err))
+    }
+
+    fn read(path: &Path) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(cache::invalid_data(
+                "not a customizable contraction hierarchy".to_string(),
+            ));
+        }
+        let mut version = [0; 8];
+        r.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version[..4].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(cache::invalid_data(format!(
+                "unsupported customizable contraction hierarchy version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let header = cache::read_header(&mut r)?;
+
+        let state_count = cache::read_u64(&mut r)? as usize;
+        let arc_count = cache::read_u64(&mut r)? as usize;
+        let nodes = cache::read_array(&mut r, state_count, i64::from_le_bytes)?;
+        let prevs = cache::read_array(&mut r, state_count, i64::from_le_bytes)?;
+        let turns = cache::read_array(&mut r, state_count, u32::from_le_bytes)?;
+        let ranks = cache::read_array(&mut r, state_count, u32::from_le_bytes)?;
+        let offsets =
+            cache::read_array(&mut r, cache::offsets_len(state_count)?, u32::from_le_bytes)?;
+        let heads = cache::read_array(&mut r, arc_count, u32::from_le_bytes)?;
+
+        let mut sorted_ranks = ranks.clone();
+        sorted_ranks.sort_unstable();
+        if offsets.last().copied() != Some(arc_count as u32)
+            || offsets.windows(2).any(|w| w[0] > w[1])
+            || heads.iter().any(|&h| h as usize >= state_count)
+            || sorted_ranks.iter().enumerate().any(|(i, &r)| i as u32 != r)
+        {
+            return Err(cache::invalid_data(
+                "arc arrays are inconsistent".to_string(),
+            ));
+        }
+
+        let states = nodes
+            .into_iter()
+            .zip(prevs)
+            .zip(turns)
+            .map(|((node, prev), turn)| {
+                (
+                    NodeId(node),
+                    turn,
+                    (prev != i64::MIN).then_some(NodeId(prev)),
+                )
+            })
+            .collect();
+        Ok(Self::new(header, states, ranks, offsets, heads))
+    }
+
+    fn arcs(&self, state: u32) -> std::ops::Range<usize> {
+        self.offsets[state as usize] as usize..self.offsets[state as usize + 1] as usize
+    }
+
+    /// The arc joining `a` and `b`, and whether it goes from `a` to `b`.
+    fn arc_between(&self, a: u32, b: u32) -> Option<(usize, bool)> {
+        let (tail, head, forward) = if self.ranks[a as usize] < self.ranks[b as usize] {
+            (a, b, true)
+        } else {
+            (b, a, false)
+        };
+        let arcs = self.arcs(tail);
+        let rank = self.ranks[head as usize];
+        self.heads[arcs.clone()]
+            .binary_search_by_key(&rank, |&h| self.ranks[h as usize])
+            .ok()
+            .map(|i| (arcs.start + i, forward))
+    }
+}
+
+fn node_state(graph: &RoadGraph, (node, turn, prev): State) -> NodeState {
+    (graph.id_of(node), turn, prev.map(|prev| graph.id_of(prev)))
+}
+
+/// Orders the states of an undirected graph so that each part of it comes
+/// before the states separating it from the others.
+///
+/// Parts are split at the middle level of a breadth-first search from a state
+/// at their periphery.
+fn nested_dissection(neighbors: &[Vec<u32>]) -> Vec<u32> {
+    let n = neighbors.len();
+    // States from the highest rank down.
+    let mut reversed = Vec::with_capacity(n);
+    let mut part_of = vec![0; n];
+    let mut levels = vec![u32::MAX; n];
+    let mut parts = vec![(0..n as u32).collect::<Vec<_>>()];
+    let mut next_part = 0;
+    while let Some(part) = parts.pop() {
+        if part.len() <= LEAF_SIZE {
+            reversed.extend(part);
+            continue;
+        }
+        next_part += 1;
+        for &state in &part {
+            part_of[state as usize] = next_part;
+        }
+        // States of the part reachable from `start` with their levels.
+        let mut bfs = |start: u32| {
+            let mut reached = vec![(start, 0)];
+            levels[start as usize] = 0;
+            let mut i = 0;
+            while let Some(&(state, level)) = reached.get(i) {
+                i += 1;
+                for &next in &neighbors[state as usize] {
+                    if part_of[next as usize] == next_part && levels[next as usize] == u32::MAX {
+                        levels[next as usize] = level + 1;
+                        reached.push((next, level + 1));
+                    }
+                }
+            }
+            for &(state, _) in &reached {
+                levels[state as usize] = u32::MAX;
+            }
+            reached
+        };
+
+        let reached = bfs(part[0]);
+        if reached.len() < part.len() {
+            // Split off the connected component of the first state.
+            for &(state, _) in &reached {
+                part_of[state as usize] = 0;
+            }
+            parts.push(
+                part.into_iter()
+                    .filter(|&state| part_of[state as usize] == next_part)
+                    .collect(),
+            );
+            parts.push(reached.into_iter().map(|(state, _)| state).collect());
+            continue;
+        }
+
+        let (periphery, _) = reached[reached.len() - 1];
+        let reached = bfs(periphery);
+        let (_, last_level) = reached[reached.len() - 1];
+        let separator_level = reached[reached.len() / 2].1;
+        if separator_level == 0 || separator_level == last_level {
+            reversed.extend(reached.into_iter().map(|(state, _)| state));
+            continue;
+        }
+        let (mut lower, mut upper) = (Vec::new(), Vec::new());
+        for (state, level) in reached {
+            match level.cmp(&separator_level) {
+                Ordering::Less => lower.push(state),
+                Ordering::Equal => reversed.push(state),
+                Ordering::Greater => upper.push(state),
+            }
+        }
+        parts.push(lower);
+        parts.push(upper);
+    }
+    reversed.reverse();
+    reversed
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{Way, WayId};
+
+    use super::*;
+    use crate::{
+        extract::Extract,
+        graph::{AdjList, Nodes, Position},
+        profile::{OnewayMode, Profile},
+        router::{
+            tests::{graph_from, grid_graph, grid_graph_with_costs, turn_costs},
+            Dijkstra, Router,
+        },
+    };
+
+    #[test]
+    fn customizable_hierarchy_follows_new_costs() {
+        // The middle row is ten times slower, so routes along it move off it.
+        let slow_row = |u: i64, v: i64| {
+            if u / 10 == 5 && v / 10 == 5 {
+                1000.0
+            } else {
+                100.0
+            }
+        };
+        for turn_costs in [None, Some(turn_costs())] {
+            let cch = CustomizableContractionHierarchy::build(
+                &grid_graph().with_turn_costs(turn_costs.clone()),
+            );
+            let graph = grid_graph_with_costs(slow_row).with_turn_costs(turn_costs);
+            let ch = cch.customize(&graph).unwrap();
+            for (start, goal) in [(50, 59), (0, 99), (45, 47), (90, 9), (52, 52)] {
+                let dijkstra = Dijkstra.route(&graph, NodeId(start), NodeId(goal)).unwrap();
+                let route = ch.route(&graph, NodeId(start), NodeId(goal)).unwrap();
+                assert_eq!(route.cost, dijkstra.cost, "{} -> {}", start, goal);
+                let ids = route.node_ids(&graph);
+                assert_eq!(ids.first(), Some(&NodeId(start)));
+                assert_eq!(ids.last(), Some(&NodeId(goal)));
+            }
+        }
+    }
+
+    #[test]
+    fn customizable_hierarchy_accepts_graphs_missing_nodes() {
+        let cch = CustomizableContractionHierarchy::build(&graph_from(&[(1, 2, 1.0), (2, 3, 1.0)]));
+
+        let graph = graph_from(&[(1, 2, 5.0)]);
+        let ch = cch.customize(&graph).unwrap();
+        let route = ch.route(&graph, NodeId(1), NodeId(2)).unwrap();
+        assert_eq!(route.cost, 5.0);
+    }
+
+    #[test]
+    fn customizable_hierarchy_rejects_other_topologies() {
+        let cch = CustomizableContractionHierarchy::build(&graph_from(&[(1, 2, 1.0), (2, 3, 1.0)]));
+
+        // An edge between known nodes that the topology lacks.
+        assert!(cch
+            .customize(&graph_from(&[(1, 2, 1.0), (2, 3, 1.0), (3, 1, 1.0)]))
+            .is_err());
+        // A node the topology lacks.
+        assert!(cch
+            .customize(&graph_from(&[(1, 2, 1.0), (2, 4, 1.0)]))
+            .is_err());
+    }
+
+    /// A road from 1 to 6: residential up to 3, primary up to 4, residential
+    /// up to 5 and then oneway.
+    fn road_graph(profile: &Profile) -> RoadGraph {
+        let way = |id, tags: &[(&str, &str)], nodes: &[i64]| Way {
+            id: WayId(id),
+            tags: tags.iter().map(|&(k, v)| (k.into(), v.into())).collect(),
+            nodes: nodes.iter().copied().map(NodeId).collect(),
+        };
+        let extract = Extract {
+            header: RoadGraph::new(Nodes::new(), AdjList::new())
+                .header()
+                .clone(),
+            nodes: (1..=6)
+                .map(|id| {
+                    let position = Position {
+                        decimicro_lat: 356_800_000 + (id as i32 % 2) * 500,
+                        decimicro_lon: 1_397_000_000 + id as i32 * 1000,
+                    };
+                    (NodeId(id), position)
+                })
+                .collect(),
+            ways: vec![
+                way(10, &[("highway", "residential")], &[1, 2, 3]),
+                way(11, &[("highway", "primary")], &[3, 4]),
+                way(12, &[("highway", "residential")], &[4, 5]),
+                way(
+                    13,
+                    &[("highway", "residential"), ("oneway", "yes")],
+                    &[5, 6],
+                ),
+            ],
+            relations: Vec::new(),
+        };
+        RoadGraph::from_extract(&extract, profile, None).unwrap()
+    }
+
+    #[test]
+    fn customizable_hierarchy_follows_profiles_changing_costs() {
+        let bicycle = Profile {
+            min_component_size: 0,
+            ..Profile::bicycle()
+        };
+        let mut costs = bicycle.clone();
+        costs.default_speed = 10.0;
+        costs.highway_speeds.insert("primary".to_string(), 25.0);
+        costs.lit_factor = 0.5;
+        costs.climb_penalty = 0.0;
+        let turns = costs.turn_costs.as_mut().unwrap();
+        turns.u_turn = 60.0;
+        turns.sharp_angle = 90.0;
+        turns.major_crossing = 0.0;
+
+        let cch = CustomizableContractionHierarchy::build(&road_graph(&bicycle));
+        let graph = road_graph(&costs);
+        let ch = cch.customize(&graph).unwrap();
+        for (start, goal) in [(1, 6), (3, 5), (5, 1), (6, 1)] {
+            let cost = |router: &dyn Router| {
+                router
+                    .route(&graph, NodeId(start), NodeId(goal))
+                    .map(|route| route.cost)
+                    .ok()
+            };
+            assert_eq!(cost(&ch), cost(&Dijkstra), "{} -> {}", start, goal);
+        }
+    }
+
+    #[test]
+    fn customizable_hierarchy_rejects_profiles_collapsing_other_chains() {
+        let bicycle = Profile {
+            min_component_size: 0,
+            ..Profile::bicycle()
+        };
+        // Without major roads 3 and 4 are inside a chain, and ignoring oneway
+        // 5 is too, but the bicycle graph has them.
+        let mut no_major = bicycle.clone();
+        no_major.turn_costs.as_mut().unwrap().major_highways.clear();
+        let mut no_oneway = bicycle.clone();
+        no_oneway.oneway = OnewayMode::Ignore;
+        for profile in [no_major, no_oneway] {
+            let cch = CustomizableContractionHierarchy::build(&road_graph(&profile));
+            assert!(matches!(
+                cch.customize(&road_graph(&bicycle)),
+                Err(Error::TopologyMismatch { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,866 @@
+//! Contraction hierarchies for answering many queries on the same graph.
+//!
+//! The hierarchy contracts the search states of [`Dijkstra`](crate::Dijkstra)
+//! rather than plain nodes, so turn restrictions and turn costs give the same
+//! routes as the other routers. Without either, there is one state per node.
+//!
+//! It is saved next to the graph cache as `graph-<profile>.ch`:
+//!
+//! | field          | type                                     |
+//! |----------------|------------------------------------------|
+//! | magic          | `b"ROSMCHHY"`                            |
+//! | version        | `u32`                                    |
+//! | padding        | 4 zeros                                  |
+//! | graph header   | as in the [graph cache](crate::cache)    |
+//! | state count    | `u64`                                    |
+//! | arc count      | `u64`                                    |
+//! | arc weights    | `[u64; arc count]`                       |
+//! | state nodes    | `[u32; state count]`                     |
+//! | previous nodes | `[u32; state count]`                     |
+//! | state turns    | `[u32; state count]`                     |
+//! | state ranks    | `[u32; state count]`                     |
+//! | arc sources    | `[u32; arc count]`                       |
+//! | arc targets    | `[u32; arc count]`                       |
+//! | arc halves     | `[(u32, u32); arc count]`                |
+//!
+//! Nodes are indices into the graph, which the header ties the hierarchy to,
+//! or `u32::MAX` for none.
+//! Weights are costs in thousandths. Shortcut arcs are made of the two arcs
+//! given as their halves, which are `u32::MAX` for arcs of the graph itself.
+//! Ranks are the order in which the states were contracted.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    fs::{self, File},
+    hash::{BuildHasherDefault, Hasher},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::{
+    cache::{self, CacheHeader},
+    error::Error,
+    graph::{Edge, NodeIdx, RoadGraph},
+    progress::{self, Settled},
+    route::Route,
+    router::{successors, to_fixed, Dijkstra, Router, State},
+};
+
+const MAGIC: &[u8; 8] = b"ROSMCHHY";
+const FORMAT_VERSION: u32 = 4;
+/// Settled states after which a witness search gives up, adding a shortcut
+/// that may not be needed.
+const WITNESS_LIMIT: usize = 500;
+/// Lower limit used to estimate how many shortcuts contracting a state adds.
+const ESTIMATE_LIMIT: usize = 5;
+const NO_HALF: u32 = u32::MAX;
+/// Previous node of the states a route starts from, and node of the states a
+/// customized topology has beyond the graph, which are never reached.
+pub(crate) const NO_NODE: NodeIdx = NodeIdx::MAX;
+
+/// A graph arc or a shortcut between two states.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChArc {
+    pub source: u32,
+    pub target: u32,
+    pub weight: u64,
+    pub halves: Option<(u32, u32)>,
+}
+
+/// A state at the other end of an arc, with the weight and index of the arc.
+#[derive(Debug, Clone, Copy)]
+struct Neighbor {
+    state: u32,
+    weight: u64,
+    arc: u32,
+}
+
+/// A contraction hierarchy over the search states of a [`RoadGraph`].
+pub struct ContractionHierarchy {
+    /// Header of the graph the hierarchy was built from.
+    header: CacheHeader,
+    states: Vec<State>,
+    ranks: Vec<u32>,
+    arcs: Vec<ChArc>,
+    state_ids: HashMap<State, u32>,
+    node_states: HashMap<NodeIdx, Vec<u32>>,
+    /// Arcs to higher ranked states, by source.
+    up: Vec<Vec<Neighbor>>,
+    /// Arcs from higher ranked states, by target.
+    down: Vec<Vec<Neighbor>>,
+}
+
+impl ContractionHierarchy {
+    /// Contracts the states of `graph` one by one, least important first.
+    pub fn build(graph: &RoadGraph) -> Self {
+        let timer = Instant::now();
+        let (states, mut arcs) = state_graph(graph);
+        let n = states.len();
+
+        let mut contraction = Contraction::new(n, &arcs);
+        let mut ranks = vec![0; n];
+        let mut contracted = vec![false; n];
+        let mut queue = (0..n as u32)
+            .map(|v| Reverse((contraction.importance(v), v)))
+            .collect::<BinaryHeap<_>>();
+
+        let bar = progress::items("Contracting", n);
+        let mut rank = 0;
+        while let Some(Reverse((_, v))) = queue.pop() {
+            if contracted[v as usize] {
+                continue;
+            }
+            // Importances go stale as neighbors are contracted, so `v` is only
+            // contracted if it is still the least important.
+            let current = contraction.importance(v);
+            if queue
+                .peek()
+                .is_some_and(|Reverse((next, _))| current > *next)
+            {
+                queue.push(Reverse((current, v)));
+                continue;
+            }
+
+            for arc in contraction.shortcuts(v, WITNESS_LIMIT) {
+                if contraction.connect(&arc, arcs.len() as u32) {
+                    arcs.push(arc);
+                }
+            }
+            contraction.remove(v);
+            contracted[v as usize] = true;
+            ranks[v as usize] = rank;
+            rank += 1;
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        let ch = Self::new(graph.header().clone(), states, ranks, arcs);
+        progress::status(format_args!(
+            "Contraction hierarchy built: {} states, {} arcs ({} s)",
+            ch.states.len(),
+            ch.arcs.len(),
+            timer.elapsed().as_secs_f64()
+        ));
+        ch
+    }
+
+    pub(crate) fn new(
+        header: CacheHeader,
+        states: Vec<State>,
+        ranks: Vec<u32>,
+        arcs: Vec<ChArc>,
+    ) -> Self {
+        let mut state_ids = HashMap::new();
+        let mut node_states = HashMap::<NodeIdx, Vec<u32>>::new();
+        for (i, &state) in states.iter().enumerate() {
+            state_ids.insert(state, i as u32);
+            node_states.entry(state.0).or_default().push(i as u32);
+        }
+        let mut up = vec![Vec::<Neighbor>::new(); states.len()];
+        let mut down = vec![Vec::<Neighbor>::new(); states.len()];
+        for (i, arc) in arcs.iter().enumerate() {
+            let (source, target) = (arc.source as usize, arc.target as usize);
+            // Loops are never on a shortest path.
+            let (list, state) = match ranks[source].cmp(&ranks[target]) {
+                Ordering::Less => (&mut up[source], arc.target),
+                Ordering::Greater => (&mut down[target], arc.source),
+                Ordering::Equal => continue,
+            };
+            // Only the cheapest of the arcs joining two states is worth
+            // following.
+            match list.iter_mut().find(|n| n.state == state) {
+                Some(n) if n.weight <= arc.weight => {}
+                Some(n) => (n.weight, n.arc) = (arc.weight, i as u32),
+                None => list.push(Neighbor {
+                    state,
+                    weight: arc.weight,
+                    arc: i as u32,
+                }),
+            }
+        }
+        ContractionHierarchy {
+            header,
+            states,
+            ranks,
+            arcs,
+            state_ids,
+            node_states,
+            up,
+            down,
+        }
+    }
+
+    /// Where the hierarchy of the graph cached for `profile_name` is saved.
+    pub fn path(cache_dir: &Path, profile_name: &str) -> PathBuf {
+        cache_dir.join(format!("graph-{}.ch", profile_name))
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(cache_dir)?;
+        let path = Self::path(cache_dir, &self.header.profile);
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        w.write_all(&[0; 4])?;
+        cache::write_header(&mut w, &self.header)?;
+
+        w.write_all(&(self.states.len() as u64).to_le_bytes())?;
+        w.write_all(&(self.arcs.len() as u64).to_le_bytes())?;
+        for arc in &self.arcs {
+            w.write_all(&arc.weight.to_le_bytes())?;
+        }
+        for (node, _, _) in &self.states {
+            w.write_all(&node.to_le_bytes())?;
+        }
+        for (_, _, prev) in &self.states {
+            w.write_all(&prev.unwrap_or(NO_NODE).to_le_bytes())?;
+        }
+        for (_, turn, _) in &self.states {
+            w.write_all(&turn.to_le_bytes())?;
+        }
+        for rank in &self.ranks {
+            w.write_all(&rank.to_le_bytes())?;
+        }
+        for arc in &self.arcs {
+            w.write_all(&arc.source.to_le_bytes())?;
+        }
+        for arc in &self.arcs {
+            w.write_all(&arc.target.to_le_bytes())?;
+        }
+        for arc in &self.arcs {
+            let (first, second) = arc.halves.unwrap_or((NO_HALF, NO_HALF));
+            w.write_all(&first.to_le_bytes())?;
+            w.write_all(&second.to_le_bytes())?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Loads the hierarchy saved for `graph`, failing if it was built from
+    /// another graph.
+    pub fn load(cache_dir: &Path, graph: &RoadGraph) -> Result<Self, Error> {
+        let path = Self::path(cache_dir, &graph.header().profile);
+        Self::read(&path, graph).map_err(|err| cache::read_error(&path, err))
+    }
+
+    fn read(path: &Path, graph: &RoadGraph) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(cache::invalid_data(
+                "not a contraction hierarchy".to_string(),
+            ));
+        }
+        let mut version = [0; 8];
+        r.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version[..4].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(cache::invalid_data(format!(
+                "unsupported contraction hierarchy version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let header = cache::read_header(&mut r)?;
+        if &header != graph.header() {
+            return Err(cache::invalid_data(
+                "built for another graph, run build-ch again".to_string(),
+            ));
+        }
+
+        let state_count = cache::read_u64(&mut r)? as usize;
+        let arc_count = cache::read_u64(&mut r)? as usize;
+        let weights = cache::read_array(&mut r, arc_count, u64::from_le_bytes)?;
+        let nodes = cache::read_array(&mut r, state_count, u32::from_le_bytes)?;
+        let prevs = cache::read_array(&mut r, state_count, u32::from_le_bytes)?;
+        let turns = cache::read_array(&mut r, state_count, u32::from_le_bytes)?;
+        let ranks = cache::read_array(&mut r, state_count, u32::from_le_bytes)?;
+        let sources = cache::read_array(&mut r, arc_count, u32::from_le_bytes)?;
+        let targets = cache::read_array(&mut r, arc_count, u32::from_le_bytes)?;
+        let halves = cache::read_array(&mut r, arc_count, |b: [u8; 8]| {
+            (
+                u32::from_le_bytes(b[..4].try_into().unwrap()),
+                u32::from_le_bytes(b[4..].try_into().unwrap()),
+            )
+        })?;
+
+        let is_node = |i: NodeIdx| i == NO_NODE || (i as usize) < graph.node_count();
+        if !nodes.iter().chain(&prevs).all(|&i| is_node(i)) {
+            return Err(cache::invalid_data(
+                "states refer to nodes the graph lacks".to_string(),
+            ));
+        }
+        let is_state = |i: u32| (i as usize) < state_count;
+        let is_arc = |i: u32| (i as usize) < arc_count;
+        if !sources.iter().chain(&targets).all(|&i| is_state(i))
+            || !halves
+                .iter()
+                .all(|&(a, b)| (a, b) == (NO_HALF, NO_HALF) || (is_arc(a) && is_arc(b)))
+        {
+            return Err(cache::invalid_data(
+                "arc arrays are inconsistent".to_string(),
+            ));
+        }
+
+        let states = nodes
+            .into_iter()
+            .zip(prevs)
+            .zip(turns)
+            .map(|((node, prev), turn)| (node, turn, (prev != NO_NODE).then_some(prev)))
+            .collect();
+        let arcs = (0..arc_count)
+            .map(|i| ChArc {
+                source: sources[i],
+                target: targets[i],
+                weight: weights[i],
+                halves: (halves[i] != (NO_HALF, NO_HALF)).then_some(halves[i]),
+            })
+            .collect();
+        Ok(Self::new(header, states, ranks, arcs))
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn arc_count(&self) -> usize {
+        self.arcs.len()
+    }
+
+    /// Appends the graph arcs `arc` is made of to `path`.
+    fn unpack(&self, arc: u32, path: &mut Vec<u32>) {
+        let mut stack = vec![arc];
+        while let Some(arc) = stack.pop() {
+            match self.arcs[arc as usize].halves {
+                Some((first, second)) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                None => path.push(arc),
+            }
+        }
+    }
+}
+
+impl Router for ContractionHierarchy {
+    /// Searches upwards from the start and the goal. `graph` must be the one
+    /// the hierarchy was built from.
+    fn find(&self, graph: &RoadGraph, start: NodeIdx, goal: NodeIdx) -> Option<Route> {
+        if start == goal {
+            return Dijkstra.find(graph, start, goal);
+        }
+
+        // On edge based graphs, the start isn't a state and the search starts
+        // from the states after each of its edges.
+        let mut first_edges = HashMap::<u32, (Edge, f64)>::new();
+        let mut forward = UpwardSearch::default();
+        match self.state_ids.get(&(start, 0, None)) {
+            Some(&id) => {
+                forward.push(id, 0, None);
+            }
+            None => {
+                for (next, edge, turn_cost) in successors(graph, (start, 0, None)) {
+                    let Some(&id) = self.state_ids.get(&next) else {
+                        continue;
+                    };
+                    if forward.push(id, to_fixed(edge.cost + turn_cost), None) {
+                        first_edges.insert(id, (*edge, turn_cost));
+                    }
+                }
+            }
+        }
+        let mut backward = UpwardSearch::default();
+        for &id in self.node_states.get(&goal).into_iter().flatten() {
+            backward.push(id, 0, None);
+        }
+
+        // Each search can stop once it only reaches states further than the
+        // best route found.
+        let mut settled = 0;
+        let progress = Settled::new();
+        let mut best: Option<(u64, u32)> = None;
+        loop {
+            let (search, other, is_forward) = match (forward.top(), backward.top()) {
+                (Some(f), Some(b)) if f <= b => (&mut forward, &backward, true),
+                (Some(_), None) => (&mut forward, &backward, true),
+                (_, Some(_)) => (&mut backward, &forward, false),
+                (None, None) => break,
+            };
+            if best.is_some_and(|(cost, _)| search.top().is_some_and(|top| top >= cost)) {
+                break;
+            }
+            let Some((state, dist)) = search.settle(self, is_forward) else {
+                continue;
+            };
+            settled += 1;
+            progress.update(settled);
+            if let Some(&other_dist) = other.dist.get(&state) {
+                if best.is_none_or(|(cost, _)| dist + other_dist < cost) {
+                    best = Some((dist + other_dist, state));
+                }
+            }
+        }
+        let (parent, child) = (forward.arcs, backward.arcs);
+
+        let (_, meeting) = best?;
+        let mut up_arcs = Vec::new();
+        let mut cur = meeting;
+        while let Some(&arc) = parent.get(&cur) {
+            up_arcs.push(arc);
+            cur = self.arcs[arc as usize].source;
+        }
+        let mut graph_arcs = Vec::new();
+        for &arc in up_arcs.iter().rev() {
+            self.unpack(arc, &mut graph_arcs);
+        }
+        let mut cur = meeting;
+        while let Some(&arc) = child.get(&cur) {
+            self.unpack(arc, &mut graph_arcs);
+            cur = self.arcs[arc as usize].target;
+        }
+
+        let mut route = Route {
+            distance: 0.0,
+            cost: 0.0,
+            nodes: vec![start],
+            settled,
+        };
+        let mut add = |edge: &Edge, turn_cost: f64, node: NodeIdx| {
+            route.distance += edge.length;
+            route.cost += edge.cost + turn_cost;
+            route.nodes.push(node);
+        };
+        let first_state = graph_arcs
+            .first()
+            .map_or(meeting, |&arc| self.arcs[arc as usize].source);
+        if let Some((edge, turn_cost)) = first_edges.get(&first_state) {
+            add(edge, *turn_cost, edge.target);
+        }
+        for arc in graph_arcs {
+            let arc = &self.arcs[arc as usize];
+            let (source, target) = (
+                self.states[arc.source as usize],
+                self.states[arc.target as usize],
+            );
+            let (_, edge, turn_cost) = successors(graph, source)
+                .filter(|(next, _, _)| *next == target)
+                .min_by_key(|(_, edge, turn_cost)| to_fixed(edge.cost + turn_cost))?;
+            add(edge, turn_cost, target.0);
+        }
+        Some(route)
+    }
+}
+
+/// One direction of a query, following arcs to higher ranked states.
+#[derive(Default)]
+struct UpwardSearch {
+    queue: BinaryHeap<Reverse<(u64, u32)>>,
+    dist: IdMap<u64>,
+    /// Arc each state was last reached by.
+    arcs: IdMap<u32>,
+}
+
+/// A map keyed by state ids, which need no protection from collisions.
+type IdMap<V> = HashMap<u32, V, BuildHasherDefault<IdHasher>>;
+
+/// Hashes a state id by multiplying it with an odd constant, which is much
+/// faster than the default hasher.
+#[derive(Default)]
+struct IdHasher(u64);
+
+impl Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u32(byte as u32);
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.0 = (self.0.rotate_left(5) ^ i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+impl UpwardSearch {
+    /// Reaches `state` at `dist` unless it was reached closer already.
+    fn push(&mut self, state: u32, dist: u64, arc: Option<u32>) -> bool {
+        if self.dist.get(&state).is_some_and(|&d| d <= dist) {
+            return false;
+        }
+        self.dist.insert(state, dist);
+        if let Some(arc) = arc {
+            self.arcs.insert(state, arc);
+        }
+        self.queue.push(Reverse((dist, state)));
+        true
+    }
+
+    fn top(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse((dist, _))| *dist)
+    }
+
+    /// Settles the closest state, or returns `None` if its queue entry was
+    /// stale.
+    ///
+    /// States that a higher ranked state reaches with a shorter path aren't on
+    /// a shortest path, so they are settled without following their arcs.
+    fn settle(&mut self, ch: &ContractionHierarchy, forward: bool) -> Option<(u32, u64)> {
+        let Reverse((dist, state)) = self.queue.pop()?;
+        if dist > self.dist[&state] {
+            return None;
+        }
+        let (next_arcs, stall_arcs) = if forward {
+            (&ch.up, &ch.down)
+        } else {
+            (&ch.down, &ch.up)
+        };
+        let stalled = stall_arcs[state as usize].iter().any(|n| {
+            self.dist
+                .get(&n.state)
+                .is_some_and(|&d| d + n.weight < dist)
+        });
+        if !stalled {
+            for n in &next_arcs[state as usize] {
+                self.push(n.state, dist + n.weight, Some(n.arc));
+            }
+        }
+        Some((state, dist))
+    }
+}
+
+/// The search states of `graph` and the arcs between them.
+pub(crate) fn state_graph(graph: &RoadGraph) -> (Vec<State>, Vec<ChArc>) {
+    let mut states = Vec::new();
+    let mut state_ids = HashMap::<State, u32>::new();
+    let mut stack = Vec::new();
+    let mut intern = |state: State, states: &mut Vec<State>, stack: &mut Vec<u32>| {
+        *state_ids.entry(state).or_insert_with(|| {
+            states.push(state);
+            stack.push((states.len() - 1) as u32);
+            (states.len() - 1) as u32
+        })
+    };
+
+    // Every node can start a route, which on edge based graphs begins in the
+    // states after its edges.
+    for node in graph.sources() {
+        if graph.is_edge_based() {
+            for (next, _, _) in successors(graph, (node, 0, None)) {
+                intern(next, &mut states, &mut stack);
+            }
+        } else {
+            intern((node, 0, None), &mut states, &mut stack);
+        }
+    }
+
+    let mut arcs = Vec::new();
+    while let Some(id) = stack.pop() {
+        for (next, edge, turn_cost) in successors(graph, states[id as usize]) {
+            let target = intern(next, &mut states, &mut stack);
+            arcs.push(ChArc {
+                source: id,
+                target,
+                weight: to_fixed(edge.cost + turn_cost),
+                halves: None,
+            });
+        }
+    }
+    (states, arcs)
+}
+
+/// The states left to contract and the arcs between them.
+struct Contraction {
+    /// Cheapest arcs leaving each state, by weight so that witness searches
+    /// can stop at the first one that is too long.
+    outgoing: Vec<Vec<Neighbor>>,
+    /// Cheapest arcs entering each state.
+    incoming: Vec<Vec<Neighbor>>,
+    /// Witness search distances, `u64::MAX` if not reached.
+    dist: Vec<u64>,
+    reached: Vec<u32>,
+    queue: BinaryHeap<Reverse<(u64, u32)>>,
+    /// Whether each state is a target of the state being contracted.
+    is_target: Vec<bool>,
+    /// Number of graph arcs each arc stands for.
+    hops: Vec<u32>,
+    /// Length of the longest chain of contracted states below each state.
+    levels: Vec<u32>,
+}
+
+impl Contraction {
+    fn new(state_count: usize, arcs: &[ChArc]) -> Self {
+        let mut this = Contraction {
+            outgoing: vec![Vec::new(); state_count],
+            incoming: vec![Vec::new(); state_count],
+            dist: vec![u64::MAX; state_count],
+            reached: Vec::new(),
+            queue: BinaryHeap::new(),
+            is_target: vec![false; state_count],
+            hops: Vec::new(),
+            levels: vec![0; state_count],
+        };
+        for (i, arc) in arcs.iter().enumerate() {
+            if arc.source != arc.target {
+                this.connect(arc, i as u32);
+            }
+        }
+        this
+    }
+
+    /// Records `arc` unless a cheaper arc joins its states already, returning
+    /// whether it did.
+    fn connect(&mut self, arc: &ChArc, id: u32) -> bool {
+        let hops = arc.halves.map_or(1, |(first, second)| {
+            self.hops[first as usize] + self.hops[second as usize]
+        });
+        self.hops.resize(self.hops.len().max(id as usize + 1), 0);
+        self.hops[id as usize] = hops;
+
+        let (source, target) = (arc.source as usize, arc.target as usize);
+        let outgoing = &mut self.outgoing[source];
+        if let Some(i) = outgoing.iter().position(|n| n.state == arc.target) {
+            if arc.weight >= outgoing[i].weight {
+                return false;
+            }
+            outgoing.remove(i);
+        }
+        let i = outgoing.partition_point(|n| n.weight <= arc.weight);
+        outgoing.insert(
+            i,
+            Neighbor {
+                state: arc.target,
+                weight: arc.weight,
+                arc: id,
+            },
+        );
+        match self.incoming[target]
+            .iter_mut()
+            .find(|n| n.state == arc.source)
+        {
+            Some(n) => (n.weight, n.arc) = (arc.weight, id),
+            None => self.incoming[target].push(Neighbor {
+                state: arc.source,
+                weight: arc.weight,
+                arc: id,
+            }),
+        }
+        true
+    }
+
+    /// Removes `v` from the graph.
+    fn remove(&mut self, v: u32) {
+        let mut neighbors = Vec::new();
+        for n in std::mem::take(&mut self.incoming[v as usize]) {
+            self.outgoing[n.state as usize].retain(|m| m.state != v);
+            neighbors.push(n.state);
+        }
+        for n in std::mem::take(&mut self.outgoing[v as usize]) {
+            self.incoming[n.state as usize].retain(|m| m.state != v);
+            neighbors.push(n.state);
+        }
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        for &u in &neighbors {
+            self.levels[u as usize] = self.levels[u as usize].max(self.levels[v as usize] + 1);
+        }
+    }
+
+    /// How late `v` should be contracted. Contracting states that replace
+    /// their arcs by fewer shortcuts, standing for fewer graph arcs, first keeps
+    /// the hierarchy small, and the level spreads it evenly over the graph.
+    fn importance(&mut self, v: u32) -> i64 {
+        let (mut added, mut added_hops) = (0, 0);
+        for arc in self.shortcuts(v, ESTIMATE_LIMIT) {
+            let (first, second) = arc.halves.unwrap();
+            added += 1;
+            added_hops += (self.hops[first as usize] + self.hops[second as usize]) as i64;
+        }
+        let (mut removed, mut removed_hops) = (0, 0);
+        for n in self.outgoing[v as usize]
+            .iter()
+            .chain(&self.incoming[v as usize])
+        {
+            removed += 1;
+            removed_hops += self.hops[n.arc as usize] as i64;
+        }
+        2 * (added - removed) + added_hops - removed_hops + self.levels[v as usize] as i64
+    }
+
+    /// Shortcuts needed to keep the distances between the neighbors of `v`
+    /// once it is contracted.
+    fn shortcuts(&mut self, v: u32, witness_limit: usize) -> Vec<ChArc> {
+        let mut shortcuts = Vec::new();
+        let incoming = std::mem::take(&mut self.incoming[v as usize]);
+        for n in &self.outgoing[v as usize] {
+            self.is_target[n.state as usize] = true;
+        }
+        for &Neighbor {
+            state: u,
+            weight: to_v,
+            arc: first,
+        } in &incoming
+        {
+            let Some(max_weight) = self.outgoing[v as usize]
+                .iter()
+                .filter(|n| n.state != u)
+                .map(|n| to_v + n.weight)
+                .max()
+            else {
+                continue;
+            };
+            self.witness_search(u, v, max_weight, witness_limit);
+            for n in &self.outgoing[v as usize] {
+                let weight = to_v + n.weight;
+                if n.state != u && self.dist[n.state as usize] > weight {
+                    shortcuts.push(ChArc {
+                        source: u,
+                        target: n.state,
+                        weight,
+                        halves: Some((first, n.arc)),
+                    });
+                }
+            }
+        }
+        self.incoming[v as usize] = incoming;
+        for n in &self.outgoing[v as usize] {
+            self.is_target[n.state as usize] = false;
+        }
+        shortcuts
+    }
+
+    /// Fills `dist` from `source` in the graph without `v`, until the targets
+    /// of `v` are settled, or up to `max_weight` or `limit` settled states.
+    fn witness_search(&mut self, source: u32, v: u32, max_weight: u64, limit: usize) {
+        for state in self.reached.drain(..) {
+            self.dist[state as usize] = u64::MAX;
+        }
+        self.queue.clear();
+
+        let mut settled = 0;
+        let mut targets_left = self.outgoing[v as usize].len();
+        self.dist[source as usize] = 0;
+        self.reached.push(source);
+        self.queue.push(Reverse((0, source)));
+        while let Some(Reverse((cur_dist, current))) = self.queue.pop() {
+            if cur_dist > self.dist[current as usize] {
+                continue;
+            }
+            if self.is_target[current as usize] {
+                targets_left -= 1;
+            }
+            settled += 1;
+            if targets_left == 0 || settled > limit {
+                break;
+            }
+            for n in &self.outgoing[current as usize] {
+                let next_dist = cur_dist + n.weight;
+                if next_dist > max_weight {
+                    break;
+                }
+                if n.state != v && next_dist < self.dist[n.state as usize] {
+                    if self.dist[n.state as usize] == u64::MAX {
+                        self.reached.push(n.state);
+                    }
+                    self.dist[n.state as usize] = next_dist;
+                    self.queue.push(Reverse((next_dist, n.state)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osmpbfreader::NodeId;
+
+    use crate::graph::{AdjList, Nodes};
+
+    const GRID_SIZE: i64 = 12;
+
+    /// A grid of streets 100 m apart whose costs vary between blocks.
+    fn grid_graph() -> RoadGraph {
+        let id = |row: i64, col: i64| NodeId(row * GRID_SIZE + col);
+        let mut adj_list = AdjList::new();
+        for row in 0..GRID_SIZE {
+            for col in 0..GRID_SIZE {
+                for (next_row, next_col) in [(row + 1, col), (row, col + 1)] {
+                    if next_row == GRID_SIZE || next_col == GRID_SIZE {
+                        continue;
+                    }
+                    let cost = [100.0, 80.0, 80.0, 60.0, 50.0][((row * 7 + col * 13) % 5) as usize];
+                    let (u, v) = (id(row, col), id(next_row, next_col));
+                    for (from, to) in [(u, v), (v, u)] {
+                        adj_list.entry(from).or_default().push(Edge {
+                            target: to,
+                            length: 100.0,
+                            cost,
+                            major: false,
+                        });
+                    }
+                }
+            }
+        }
+        RoadGraph::new(Nodes::new(), adj_list)
+    }
+
+    /// Routes between pairs of nodes spread over the grid.
+    fn pairs(graph: &RoadGraph) -> impl Iterator<Item = (NodeIdx, NodeIdx)> {
+        let n = graph.node_count() as NodeIdx;
+        (0..n)
+            .step_by(5)
+            .flat_map(move |start| (0..n).step_by(7).map(move |goal| (start, goal)))
+    }
+
+    #[test]
+    fn hierarchies_find_dijkstra_routes_settling_fewer_states() {
+        let graph = grid_graph();
+        let ch = ContractionHierarchy::build(&graph);
+
+        let mut ranks = ch.ranks.clone();
+        ranks.sort_unstable();
+        assert!(ranks.into_iter().eq(0..ch.state_count() as u32));
+
+        let (mut ch_settled, mut dijkstra_settled) = (0, 0);
+        for (start, goal) in pairs(&graph) {
+            let dijkstra = Dijkstra.find(&graph, start, goal).unwrap();
+            let route = ch.find(&graph, start, goal).unwrap();
+            assert!(
+                (route.cost - dijkstra.cost).abs() < 1e-6,
+                "{} -> {}",
+                start,
+                goal
+            );
+            assert_eq!(route.nodes.first(), Some(&start));
+            assert_eq!(route.nodes.last(), Some(&goal));
+            ch_settled += route.settled;
+            dijkstra_settled += dijkstra.settled;
+        }
+        assert!(ch_settled * 2 < dijkstra_settled);
+    }
+
+    #[test]
+    fn hierarchies_are_loaded_as_saved() {
+        let graph = grid_graph();
+        let ch = ContractionHierarchy::build(&graph);
+        let dir = std::env::temp_dir().join(format!("read-osm-ch-{}", std::process::id()));
+        ch.save(&dir).unwrap();
+        let loaded = ContractionHierarchy::load(&dir, &graph);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.state_count(), ch.state_count());
+        assert_eq!(loaded.arc_count(), ch.arc_count());
+        for (start, goal) in pairs(&graph) {
+            assert_eq!(
+                loaded.find(&graph, start, goal).map(|route| route.nodes),
+                ch.find(&graph, start, goal).map(|route| route.nodes)
+            );
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Strongly connected components of the road graph.
+
+use itertools::Itertools;
+
+use crate::graph::{NodeIdx, RoadGraph};
+
+/// Strongly connected components of a [`RoadGraph`], numbered from the
+/// largest one. Routes exist between any two nodes of the same component.
+pub struct Components {
+    /// Component by node index, `NONE` for nodes in none.
+    component: Vec<usize>,
+    /// Number of nodes by component, in descending order.
+    sizes: Vec<usize>,
+}
+
+const NONE: usize = usize::MAX;
+
+impl Components {
+    /// Finds the components with Tarjan's algorithm over the junctions. The
+    /// nodes inside an edge belong to the component of its ends if they share
+    /// one, and to none otherwise.
+    pub fn new(graph: &RoadGraph) -> Self {
+        let n = graph.node_count();
+
+        const UNVISITED: usize = usize::MAX;
+        let mut index = vec![UNVISITED; n];
+        let mut low = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut component = vec![NONE; n];
+        let mut stack = Vec::new();
+        let mut raw_sizes = Vec::new();
+        let mut next_index = 0;
+        for root in 0..n {
+            if index[root] != UNVISITED || !graph.is_junction(root as NodeIdx) {
+                continue;
+            }
+            // Nodes whose edges are being visited, with the next edge to visit.
+            let mut path = vec![(root, 0)];
+            index[root] = next_index;
+            low[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            while let Some(&mut (v, ref mut next_edge)) = path.last_mut() {
+                if let Some(edge) = graph.edges(v as NodeIdx).get(*next_edge) {
+                    *next_edge += 1;
+                    let w = edge.target as usize;
+                    if index[w] == UNVISITED {
+                        index[w] = next_index;
+                        low[w] = next_index;
+                        next_index += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        path.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+
+                path.pop();
+                if let Some(&(u, _)) = path.last() {
+                    low[u] = low[u].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    let mut size = 0;
+                    while let Some(w) = stack.pop() {
+                        on_stack[w] = false;
+                        component[w] = raw_sizes.len();
+                        size += 1;
+                        if w == v {
+                            break;
+                        }
+                    }
+                    raw_sizes.push(size);
+                }
+            }
+        }
+
+        for source in graph.sources() {
+            for edge in graph.edges(source) {
+                let c = component[source as usize];
+                if c == component[edge.target as usize] {
+                    for via in graph.geometry(source, edge.target) {
+                        if component[via.node as usize] == NONE {
+                            component[via.node as usize] = c;
+                            raw_sizes[c] += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Renumber from the largest component down.
+        let order = (0..raw_sizes.len())
+            .sorted_by_key(|&c| (std::cmp::Reverse(raw_sizes[c]), c))
+            .collect_vec();
+        let mut rank = vec![0; raw_sizes.len()];
+        for (i, &c) in order.iter().enumerate() {
+            rank[c] = i;
+        }
+        for c in component.iter_mut().filter(|c| **c != NONE) {
+            *c = rank[*c];
+        }
+        Components {
+            component,
+            sizes: order.iter().map(|&c| raw_sizes[c]).collect(),
+        }
+    }
+
+    /// Component of `idx`, 0 being the largest one.
+    pub fn of(&self, idx: NodeIdx) -> Option<usize> {
+        self.component
+            .get(idx as usize)
+            .copied()
+            .filter(|&c| c != NONE)
+    }
+
+    /// Whether `idx` is in the largest component.
+    pub fn is_main(&self, idx: NodeIdx) -> bool {
+        self.of(idx) == Some(0)
+    }
+
+    /// Number of nodes by component, from the largest one.
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use osmpbfreader::{Node, NodeId, Tags};
+
+    use super::*;
+    use crate::graph::{AdjList, Edge};
+
+    /// A triangle 1, 2, 3 with a dead end 3 - 8 - 9, a oneway 3 -> 4 and an
+    /// island 5 - 6, with every edge of unit length.
+    pub(crate) fn island_graph() -> RoadGraph {
+        let mut adj_list = AdjList::new();
+        let mut add = |u: i64, v: i64| {
+            adj_list.entry(NodeId(u)).or_default().push(Edge {
+                target: NodeId(v),
+                length: 1.0,
+                cost: 1.0,
+                major: false,
+            })
+        };
+        for (u, v) in [(1, 2), (2, 3), (3, 1), (3, 8), (8, 9), (5, 6)] {
+            add(u, v);
+            add(v, u);
+        }
+        add(3, 4);
+        let nodes = [1, 2, 3, 4, 5, 6, 8, 9]
+            .map(|id| {
+                let node = Node {
+                    id: NodeId(id),
+                    tags: Tags::new(),
+                    decimicro_lat: 356_800_000 + id as i32 * 1000,
+                    decimicro_lon: 1_397_600_000,
+                };
+                (node.id, node)
+            })
+            .into();
+        RoadGraph::new(nodes, adj_list).collapse_chains()
+    }
+
+    #[test]
+    fn components_are_numbered_from_the_largest() {
+        let graph = island_graph();
+        let idx = |id| graph.index_of(NodeId(id)).unwrap();
+        assert!(!graph.is_junction(idx(8)));
+
+        let components = Components::new(&graph);
+        assert_eq!(components.sizes(), [5, 2, 1]);
+        for id in [1, 2, 3, 8, 9] {
+            assert!(components.is_main(idx(id)), "{}", id);
+        }
+        assert_eq!(components.of(idx(5)), Some(1));
+        assert_eq!(components.of(idx(6)), Some(1));
+        assert_eq!(components.of(idx(4)), Some(2));
+    }
+
+    #[test]
+    fn small_components_are_dropped() {
+        let graph = island_graph().drop_small_components(2);
+        assert!(!graph.contains(NodeId(4)));
+        assert!(graph.contains(NodeId(5)));
+        let idx = |id| graph.index_of(NodeId(id)).unwrap();
+        assert!(graph
+            .edges(idx(3))
+            .iter()
+            .all(|edge| graph.id_of(edge.target) != NodeId(4)));
+
+        let graph = graph.drop_small_components(3);
+        let idx = |id| graph.index_of(NodeId(id)).unwrap();
+        assert_eq!(graph.node_count(), 5);
+        assert_eq!(graph.edge_count(), 8);
+        assert_eq!(graph.geometry(idx(3), idx(9)).len(), 1);
+        assert_eq!(graph.geometry(idx(3), idx(9))[0].node, idx(8));
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use reqwest::{
+    blocking::{Client, Response},
+    header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    StatusCode,
+};
+
+use crate::{error::Error, progress};
+
+const GEOFABRIK_URL: &str = "https://download.geofabrik.de";
+/// Geofabrik region downloaded unless another one is chosen.
+pub const DEFAULT_REGION: &str = "asia/japan";
+
+/// URL of the latest extract of a Geofabrik `region`, e.g. `europe/germany`.
+pub fn geofabrik_url(region: &str) -> String {
+    format!(
+        "{}/{}-latest.osm.pbf",
+        GEOFABRIK_URL,
+        region.trim_matches('/')
+    )
+}
+
+/// URL of the replication server publishing the diffs of a Geofabrik
+/// `region`, for keeping a graph up to date with
+/// [`update`](crate::osc::OsmChange).
+pub fn geofabrik_updates_url(region: &str) -> String {
+    format!("{}/{}-updates", GEOFABRIK_URL, region.trim_matches('/'))
+}
+
+/// Name of the file `url` points to, for saving it under the same name.
+pub fn file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Where `pbf_path` is downloaded to before it is verified and renamed.
+fn part_path(pbf_path: &Path) -> PathBuf {
+    let mut name = pbf_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    pbf_path.with_file_name(name)
+}
+
+/// Where the validator of the response the partial file of `pbf_path` comes
+/// from is kept, to only resume it from the same file.
+fn validator_path(pbf_path: &Path) -> PathBuf {
+    let mut name = part_path(pbf_path).into_os_string();
+    name.push(".validator");
+    PathBuf::from(name)
+}
+
+/// What `If-Range` can tell the file `resp` sends by: its strong `ETag`, or
+/// else its `Last-Modified` date.
+fn validator(resp: &Response) -> Option<String> {
+    let header = |name| resp.headers().get(name)?.to_str().ok();
+    header(ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Downloads the extract at `url` to `pbf_path`, resuming the partial file an
+/// interrupted download left and checking it against `<url>.md5` if the
+/// server has it. The file only appears at `pbf_path` once it is complete.
+///
+/// A partial file is only resumed if the server still has the file it is part
+/// of, which `If-Range` asks for with the `ETag` or `Last-Modified` saved with
+/// it. Otherwise the download starts over.
+pub fn download_pbf(url: &str, pbf_path: &Path) -> Result<(), Error> {
+    // Extracts take long enough to download that the default timeout of the
+    // whole request would cut them off.
+    let client = Client::builder().timeout(None).build()?;
+    let part = part_path(pbf_path);
+    let validator_file = validator_path(pbf_path);
+    let bad_download = |reason: String| {
+        fs::remove_file(&part)?;
+        remove_if_exists(&validator_file)?;
+        Err(Error::BadDownload {
+            url: url.to_string(),
+            reason,
+        })
+    };
+
+    let offset = fs::metadata(&part).map_or(0, |metadata| metadata.len());
+    let saved_validator = fs::read_to_string(&validator_file).ok();
+    let mut request = client.get(url);
+    if let (true, Some(saved_validator)) = (offset > 0, &saved_validator) {
+        request = request
+            .header(RANGE, format!("bytes={}-", offset))
+            .header(IF_RANGE, saved_validator.as_str());
+    }
+    let resp = request.send()?;
+    // A server that can't resume, or whose file changed, sends the whole file
+    // again, and one that has nothing after `offset` means the partial file is
+    // already whole.
+    if resp.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+        let mut resp = resp.error_for_status()?;
+        let resumed = resp.status() == StatusCode::PARTIAL_CONTENT;
+        let file = if resumed {
+            let content_range = resp
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if !content_range.starts_with(&format!("bytes {}-", offset)) {
+                return bad_download(format!(
+                    "asked for bytes from {} but got range {:?}",
+                    offset, content_range
+                ));
+            }
+            OpenOptions::new().append(true).open(&part)?
+        } else {
+            match validator(&resp) {
+                Some(validator) => fs::write(&validator_file, validator)?,
+                None => remove_if_exists(&validator_file)?,
+            }
+            File::create(&part)?
+        };
+        let start = if resumed { offset } else { 0 };
+        let bar = progress::bytes("Downloading", resp.content_length().map(|len| start + len));
+        bar.set_position(start);
+        let mut pbf_file = bar.wrap_write(BufWriter::new(file));
+        resp.copy_to(&mut pbf_file)?;
+        pbf_file.flush()?;
+        bar.finish_and_clear();
+    }
+
+    // Servers without checksums may answer with any error or none at all,
+    // which leaves the download unverified rather than failed.
+    let md5_url = format!("{}.md5", url);
+    let checksum = match client.get(&md5_url).send() {
+        Ok(resp) if resp.status().is_success() => Ok(resp),
+        Ok(resp) => Err(resp.status().to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    match checksum {
+        Err(reason) => progress::status(format_args!(
+            "No checksum at {} ({}), the download is unverified",
+            md5_url, reason
+        )),
+        Ok(resp) => {
+            let text = resp.text()?;
+            let expected = text.split_whitespace().next().unwrap_or_default();
+            let actual = file_md5(&part)?;
+            if !expected.eq_ignore_ascii_case(&actual) {
+                return bad_download(format!("MD5 is {} but {} expected", actual, expected));
+            }
+            progress::status(format_args!("Checksum verified: {}", actual));
+        }
+    }
+    fs::rename(&part, pbf_path)?;
+    remove_if_exists(&validator_file)?;
+    Ok(())
+}
+
+fn file_md5(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.consume(&buf[..n]);
+    }
+    Ok(format!("{:x}", context.compute()))
+}
+
+/// Downloads the extract at `url` unless `pbf_path` already exists. A failed
+/// download is resumed the next time.
+pub fn ensure_pbf(url: &str, pbf_path: &Path) -> Result<(), Error> {
+    if !pbf_path.exists() {
+        if let Some(dir) = pbf_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        download_pbf(url, pbf_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::*;
+
+    /// A request for the extract, with its `Range` and `If-Range` if any.
+    type Request = (Option<String>, Option<String>);
+
+    /// Serves `body` at `/test.osm.pbf` with the ETag `"v1"`, honouring ranges
+    /// unless `If-Range` names another version, and next to it `md5`, or the
+    /// error status it is, or no response if that is empty. Returns the base
+    /// URL and the requests so far.
+    fn serve(
+        body: Vec<u8>,
+        md5: Result<String, &'static str>,
+    ) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requested = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines().map(Result::unwrap);
+                let path = lines.next().unwrap().split(' ').nth(1).unwrap().to_string();
+                let (mut range, mut if_range) = (None, None);
+                for line in lines.take_while(|line| !line.is_empty()) {
+                    let (name, value) = line.split_once(": ").unwrap();
+                    match name.to_lowercase().as_str() {
+                        "range" => range = Some(value.trim_start_matches("bytes=").to_string()),
+                        "if-range" => if_range = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+                let offset = range
+                    .as_deref()
+                    .filter(|_| if_range.as_deref().is_none_or(|v| v == "\"v1\""))
+                    .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+                if path == "/test.osm.pbf" {
+                    requested.lock().unwrap().push((range, if_range));
+                }
+                if path == "/test.osm.pbf.md5" && md5 == Err("") {
+                    continue;
+                }
+                let (status, headers, content) = match (path.as_str(), offset, &md5) {
+                    ("/test.osm.pbf", Some(offset), _) => (
+                        "206 Partial Content",
+                        format!(
+                            "Content-Range: bytes {}-{}/{}\r\n",
+                            offset,
+                            body.len() - 1,
+                            body.len()
+                        ),
+                        body[offset..].to_vec(),
+                    ),
+                    ("/test.osm.pbf", None, _) => ("200 OK", String::new(), body.clone()),
+                    ("/test.osm.pbf.md5", _, Ok(md5)) => (
+                        "200 OK",
+                        String::new(),
+                        format!("{}  test.osm.pbf\n", md5).into_bytes(),
+                    ),
+                    ("/test.osm.pbf.md5", _, Err(status)) => (*status, String::new(), Vec::new()),
+                    _ => ("404 Not Found", String::new(), Vec::new()),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nETag: \"v1\"\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    headers,
+                    content.len()
+                )
+                .unwrap();
+                stream.write_all(&content).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn temp_pbf_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("read-osm-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("test.osm.pbf")
+    }
+
+    fn body() -> Vec<u8> {
+        (0..100_000).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Downloads `body` over the partial file `part` saved with `validator`,
+    /// returning the requests for the extract, whether the download matches
+    /// `body` and whether it left files behind.
+    fn download_over(
+        name: &str,
+        md5: Result<String, &'static str>,
+        part: &[u8],
+        validator: Option<&str>,
+    ) -> (Vec<Request>, bool, bool) {
+        let body = body();
+        let (url, requests) = serve(body.clone(), md5);
+        let pbf_path = temp_pbf_path(name);
+        fs::write(part_path(&pbf_path), part).unwrap();
+        if let Some(validator) = validator {
+            fs::write(validator_path(&pbf_path), validator).unwrap();
+        }
+
+        download_pbf(&format!("{}/test.osm.pbf", url), &pbf_path).unwrap();
+        let downloaded = fs::read(&pbf_path).unwrap();
+        let left = part_path(&pbf_path).exists() || validator_path(&pbf_path).exists();
+        fs::remove_dir_all(pbf_path.parent().unwrap()).unwrap();
+        let requests = requests.lock().unwrap().clone();
+        (requests, downloaded == body, left)
+    }
+
+    fn md5() -> Result<String, &'static str> {
+        Ok(format!("{:x}", md5::compute(body())))
+    }
+
+    #[test]
+    fn downloads_resume_partial_files_and_are_verified() {
+        let (requests, matches, left) =
+            download_over("resume", md5(), &body()[..40_000], Some("\"v1\""));
+        assert_eq!(
+            requests,
+            [(Some("40000-".to_string()), Some("\"v1\"".to_string()))]
+        );
+        assert!(matches);
+        assert!(!left);
+    }
+
+    #[test]
+    fn downloads_of_changed_files_start_over() {
+        let (requests, matches, left) =
+            download_over("changed", md5(), &[0; 40_000], Some("\"v0\""));
+        assert_eq!(
+            requests,
+            [(Some("40000-".to_string()), Some("\"v0\"".to_string()))]
+        );
+        assert!(matches);
+        assert!(!left);
+
+        // Without a validator, the partial file can't be told apart from
+        // another version.
+        let (requests, matches, _) = download_over("unknown", md5(), &[0; 40_000], None);
+        assert_eq!(requests, [(None, None)]);
+        assert!(matches);
+    }
+
+    #[test]
+    fn downloads_without_a_checksum_are_unverified() {
+        for (name, status) in [
+            ("no-md5", "404 Not Found"),
+            ("md5-error", "500 Internal Server Error"),
+            ("md5-unreachable", ""),
+        ] {
+            let (_, matches, left) = download_over(name, Err(status), &[], None);
+            assert!(matches, "{}", status);
+            assert!(!left, "{}", status);
+        }
+    }
+
+    #[test]
+    fn downloads_not_matching_their_checksum_are_discarded() {
+        let (url, _) = serve(vec![1; 1000], Ok(format!("{:x}", md5::compute([2]))));
+        let pbf_path = temp_pbf_path("checksum");
+
+        let result = download_pbf(&format!("{}/test.osm.pbf", url), &pbf_path);
+        let left = pbf_path.exists()
+            || part_path(&pbf_path).exists()
+            || validator_path(&pbf_path).exists();
+        fs::remove_dir_all(pbf_path.parent().unwrap()).unwrap();
+        assert!(matches!(result, Err(Error::BadDownload { .. })));
+        assert!(!left);
+    }
+}
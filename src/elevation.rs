@@ -0,0 +1,236 @@
+//! Elevation lookups from a directory of SRTM `.hgt` tiles.
+//!
+//! Each tile covers one degree square and is named after its south west corner,
+//! e.g. `N35E139.hgt`. It holds a square grid of big-endian `i16` heights in
+//! meters, row by row from north to south, at either 3 (1201 × 1201) or
+//! 1 (3601 × 3601) arc second resolution.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::fingerprint::fnv1a;
+
+const VOID: i16 = -32768;
+
+/// A lazily loaded set of HGT tiles.
+pub struct Dem {
+    dir: PathBuf,
+    tiles: HashMap<(i32, i32), Option<HgtTile>>,
+}
+
+struct HgtTile {
+    size: usize,
+    heights: Vec<i16>,
+}
+
+impl Dem {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("DEM directory {} doesn't exist", dir.display()),
+            ));
+        }
+        Ok(Dem {
+            dir: dir.to_path_buf(),
+            tiles: HashMap::new(),
+        })
+    }
+
+    /// Height in meters at a point, bilinearly interpolated, or `None` if there
+    /// is no tile covering it or the surrounding samples are voids.
+    pub fn elevation(&mut self, lat: f64, lon: f64) -> io::Result<Option<f64>> {
+        let key = (lat.floor() as i32, lon.floor() as i32);
+        if !self.tiles.contains_key(&key) {
+            let tile = HgtTile::load(&self.dir.join(tile_name(key.0, key.1)))?;
+            self.tiles.insert(key, tile);
+        }
+        Ok(self.tiles[&key]
+            .as_ref()
+            .and_then(|tile| tile.interpolate(lat - key.0 as f64, lon - key.1 as f64)))
+    }
+}
+
+impl HgtTile {
+    /// Reads a tile, or returns `None` if the file doesn't exist.
+    fn load(path: &Path) -> io::Result<Option<Self>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let size = ((bytes.len() / 2) as f64).sqrt() as usize;
+        if size < 2 || size * size * 2 != bytes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a square HGT tile", path.display()),
+            ));
+        }
+        Ok(Some(HgtTile {
+            size,
+            heights: bytes
+                .chunks_exact(2)
+                .map(|b| i16::from_be_bytes([b[0], b[1]]))
+                .collect(),
+        }))
+    }
+
+    /// `dlat` and `dlon` are the offsets in degrees from the south west corner.
+    fn interpolate(&self, dlat: f64, dlon: f64) -> Option<f64> {
+        let last = (self.size - 1) as f64;
+        let y = ((1.0 - dlat) * last).clamp(0.0, last);
+        let x = (dlon * last).clamp(0.0, last);
+        let (row, col) = (
+            (y.floor() as usize).min(self.size - 2),
+            (x.floor() as usize).min(self.size - 2),
+        );
+        let (fy, fx) = (y - row as f64, x - col as f64);
+
+        let at = |r: usize, c: usize| match self.heights[r * self.size + c] {
+            VOID => None,
+            h => Some(h as f64),
+        };
+        let top = at(row, col)? * (1.0 - fx) + at(row, col + 1)? * fx;
+        let bottom = at(row + 1, col)? * (1.0 - fx) + at(row + 1, col + 1)? * fx;
+        Some(top * (1.0 - fy) + bottom * fy)
+    }
+}
+
+/// Hash of the names, sizes and modification times of the tiles in `dir`, to
+/// tell when tiles have been added, removed or replaced.
+pub fn tiles_fingerprint(dir: &Path) -> io::Result<u64> {
+    let mut tiles = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.ends_with(".hgt") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        tiles.push(format!("{} {} {}\n", name, metadata.len(), modified));
+    }
+    tiles.sort_unstable();
+    Ok(fnv1a(tiles.concat().into_bytes()))
+}
+
+fn tile_name(lat: i32, lon: i32) -> String {
+    format!(
+        "{}{:02}{}{:03}.hgt",
+        if lat >= 0 { 'N' } else { 'S' },
+        lat.abs(),
+        if lon >= 0 { 'E' } else { 'W' },
+        lon.abs()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("read-osm-dem-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a tile of `heights`, given row by row from north to south.
+    fn write_tile(dir: &Path, name: &str, heights: &[i16]) {
+        let bytes = heights
+            .iter()
+            .flat_map(|h| h.to_be_bytes())
+            .collect::<Vec<_>>();
+        fs::write(dir.join(name), bytes).unwrap();
+    }
+
+    #[test]
+    fn tiles_are_named_after_their_south_west_corner() {
+        assert_eq!(tile_name(35, 139), "N35E139.hgt");
+        assert_eq!(tile_name(0, 0), "N00E000.hgt");
+        assert_eq!(tile_name(-1, -1), "S01W001.hgt");
+        assert_eq!(tile_name(-34, -180), "S34W180.hgt");
+    }
+
+    #[test]
+    fn heights_are_bilinearly_interpolated() {
+        // 100 m at the north west corner, rising 100 m towards the east and
+        // falling 100 m towards the south.
+        let tile = HgtTile {
+            size: 2,
+            heights: vec![100, 200, 0, 100],
+        };
+        for (dlat, dlon) in [(0.0, 0.0), (1.0, 1.0), (0.5, 0.5), (0.25, 0.6), (0.9, 0.1)] {
+            let height = tile.interpolate(dlat, dlon).unwrap();
+            assert!(
+                (height - 100.0 * (dlat + dlon)).abs() < 1e-9,
+                "{} {}",
+                dlat,
+                dlon
+            );
+        }
+    }
+
+    #[test]
+    fn voids_only_hide_the_cells_around_them() {
+        // The north west corner of a 3 × 3 tile is a void.
+        let tile = HgtTile {
+            size: 3,
+            heights: vec![VOID, 10, 10, 10, 10, 10, 10, 10, 10],
+        };
+        assert_eq!(tile.interpolate(0.9, 0.1), None);
+        assert_eq!(tile.interpolate(0.1, 0.9), Some(10.0));
+        assert_eq!(tile.interpolate(0.9, 0.9), Some(10.0));
+    }
+
+    #[test]
+    fn points_are_looked_up_in_the_tile_covering_them() {
+        let dir = temp_dir("lookup");
+        write_tile(&dir, "N35E139.hgt", &[100, 200, 0, 100]);
+        write_tile(&dir, "S01W001.hgt", &[-5, -5, -5, -5]);
+        let mut dem = Dem::new(&dir).unwrap();
+        let tokyo = dem.elevation(35.5, 139.25).unwrap();
+        let south_west = dem.elevation(-0.5, -0.5).unwrap();
+        let missing = dem.elevation(36.5, 139.5).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(tokyo, Some(75.0));
+        assert_eq!(south_west, Some(-5.0));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn tiles_that_are_not_square_are_rejected() {
+        let dir = temp_dir("square");
+        write_tile(&dir, "N35E139.hgt", &[1, 2, 3]);
+        let result = Dem::new(&dir).unwrap().elevation(35.5, 139.5);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn fingerprints_change_with_the_tiles() {
+        let dir = temp_dir("fingerprint");
+        write_tile(&dir, "N35E139.hgt", &[1, 2, 3, 4]);
+        fs::write(dir.join("README.txt"), "not a tile").unwrap();
+        let before = tiles_fingerprint(&dir).unwrap();
+        fs::write(dir.join("README.txt"), "still not a tile").unwrap();
+        let other_file = tiles_fingerprint(&dir).unwrap();
+        write_tile(&dir, "N35E139.hgt", &[1; 9]);
+        let replaced = tiles_fingerprint(&dir).unwrap();
+        write_tile(&dir, "N36E139.hgt", &[1, 2, 3, 4]);
+        let added = tiles_fingerprint(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(before, other_file);
+        assert_ne!(before, replaced);
+        assert_ne!(replaced, added);
+    }
+}
@@ -0,0 +1,111 @@
+//! Errors returned by the library.
+
+use std::{fmt, io, path::PathBuf};
+
+use osmpbfreader::NodeId;
+
+use crate::profile::ProfileError;
+
+/// Why building, loading or routing on a graph failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The graph has no node with this ID.
+    NodeNotFound(NodeId),
+    /// No route leads from `start` to `goal`.
+    Unreachable {
+        start: NodeId,
+        goal: NodeId,
+    },
+    /// A cache file can't be used for `reason`.
+    CacheCorrupt {
+        path: PathBuf,
+        reason: String,
+    },
+    /// A hierarchy from `build-cch` was ordered for another graph.
+    TopologyMismatch {
+        pbf: String,
+        profile: String,
+    },
+    /// Fetching an extract failed.
+    Download(reqwest::Error),
+    /// The extract fetched from `url` is corrupt, e.g. doesn't match its
+    /// checksum, and was deleted.
+    BadDownload {
+        url: String,
+        reason: String,
+    },
+    /// An OsmChange diff or replication state at `location` can't be read.
+    OsmChange {
+        location: String,
+        reason: String,
+    },
+    Profile(ProfileError),
+    Pbf(osmpbfreader::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NodeNotFound(id) => write!(f, "node {} is not in the graph", id.0),
+            Error::Unreachable { start, goal } => {
+                write!(f, "no route from node {} to node {}", start.0, goal.0)
+            }
+            Error::CacheCorrupt { path, reason } => {
+                write!(f, "cannot use {}: {}", path.display(), reason)
+            }
+            Error::TopologyMismatch { pbf, profile } => write!(
+                f,
+                "the graph doesn't match the topology built from {} with profile {}, \
+                 run build-cch again",
+                pbf, profile
+            ),
+            Error::Download(err) => write!(f, "download failed: {}", err),
+            Error::BadDownload { url, reason } => {
+                write!(f, "bad download from {}: {}", url, reason)
+            }
+            Error::OsmChange { location, reason } => {
+                write!(f, "cannot read {}: {}", location, reason)
+            }
+            Error::Profile(err) => err.fmt(f),
+            Error::Pbf(err) => write!(f, "cannot read the PBF: {}", err),
+            Error::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Download(err) => Some(err),
+            Error::Profile(err) => Some(err),
+            Error::Pbf(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Download(err)
+    }
+}
+
+impl From<ProfileError> for Error {
+    fn from(err: ProfileError) -> Self {
+        Error::Profile(err)
+    }
+}
+
+impl From<osmpbfreader::Error> for Error {
+    fn from(err: osmpbfreader::Error) -> Self {
+        Error::Pbf(err)
+    }
+}
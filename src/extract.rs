@@ -0,0 +1,527 @@
+//! The OSM data a [`RoadGraph`](crate::RoadGraph) is built from, kept next to
+//! the graph cache so that [OsmChange](crate::osc) diffs can be applied to it
+//! and the graph rebuilt without reading the PBF again.
+//!
+//! It holds the ways the profile accepts, the positions of their nodes and the
+//! turn restrictions, and is saved as `graph-<profile>.extract`:
+//!
+//! | field          | type                                        |
+//! |----------------|---------------------------------------------|
+//! | magic          | `b"ROSMXTRC"`                               |
+//! | version        | `u32`                                       |
+//! | padding        | 4 zeros                                     |
+//! | graph header   | as in the [graph cache](crate::cache)       |
+//! | node count     | `u64`                                       |
+//! | way count      | `u64`                                       |
+//! | relation count | `u64`                                       |
+//! | node IDs       | `[i64; node count]`                         |
+//! | coordinates    | `[(i32, i32); node count]`                  |
+//! | ways           | ID `i64`, `u32` node count, `[i64]`, tags   |
+//! | relations      | ID `i64`, `u32` member count, members, tags |
+//!
+//! A member is its type, `0` for nodes, `1` for ways and `2` for relations, as
+//! `u8`, its ID as `i64` and its role. Tags are a `u32` count of key and value
+//! pairs, and strings a `u32` length and UTF-8. Everything is sorted by ID.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use itertools::{EitherOrBoth, Itertools};
+use osmpbfreader::{
+    NodeId, OsmId, OsmObj, OsmPbfReader, Ref, Relation, RelationId, Tags, Way, WayId,
+};
+
+use crate::{
+    cache::{self, CacheHeader},
+    error::Error,
+    graph::{expected_header, NodeIds, Position, RoadGraph},
+    osc::{Action, OsmChange},
+    profile::{Direction, Profile},
+    progress,
+};
+
+const MAGIC: &[u8; 8] = b"ROSMXTRC";
+const FORMAT_VERSION: u32 = 1;
+
+/// The routable part of an extract for one profile.
+pub struct Extract {
+    /// Header of the graph built from the extract.
+    pub header: CacheHeader,
+    /// Positions of the nodes of `ways`, unless missing from the extract or
+    /// from the diffs that made a way routable.
+    pub nodes: Vec<(NodeId, Position)>,
+    /// Ways the profile accepts.
+    pub ways: Vec<Way>,
+    /// Relations the profile takes as turn restrictions.
+    pub relations: Vec<Relation>,
+}
+
+impl Extract {
+    /// Reads the ways `profile` accepts from a PBF, with the positions of their
+    /// nodes and the turn restrictions, for a graph taking node elevations
+    /// from `dem_dir` if given.
+    pub fn from_pbf(
+        pbf_path: &Path,
+        profile: &Profile,
+        dem_dir: Option<&Path>,
+    ) -> Result<Self, Error> {
+        let timer = Instant::now();
+        let header = expected_header(pbf_path, profile, dem_dir)?;
+
+        // Nodes are most of an extract, so the ways are read first to only
+        // keep the nodes they go through, and only their coordinates.
+        let pbf_file = File::open(pbf_path)?;
+        let bar = progress::bytes("Reading ways", Some(pbf_file.metadata()?.len()));
+        let mut pbf_reader = OsmPbfReader::new(BufReader::new(bar.wrap_read(pbf_file)));
+        let mut ways = Vec::<Way>::new();
+        let mut relations = Vec::<Relation>::new();
+        let mut needed = HashSet::new();
+        for osm_obj in pbf_reader.par_iter() {
+            match osm_obj? {
+                OsmObj::Way(way) if accepts(profile, &way) => {
+                    needed.extend(way.nodes.iter().copied());
+                    ways.push(way);
+                }
+                OsmObj::Relation(relation) if profile.restriction(&relation.tags).is_some() => {
+                    relations.push(relation)
+                }
+                _ => {}
+            }
+        }
+        ways.sort_unstable_by_key(|way| way.id);
+        relations.sort_unstable_by_key(|relation| relation.id);
+        let mut needed = needed.into_iter().collect_vec();
+        needed.sort_unstable();
+        let ids = NodeIds::new(needed);
+
+        let mut positions = vec![Position::UNKNOWN; ids.len()];
+        pbf_reader.rewind()?;
+        bar.set_message("Reading nodes");
+        for osm_obj in pbf_reader.par_iter() {
+            if let OsmObj::Node(node) = osm_obj? {
+                if let Some(idx) = ids.index(node.id) {
+                    positions[idx as usize] = Position::of(&node);
+                }
+            }
+        }
+        bar.finish_and_clear();
+        let nodes = (0..ids.len() as u32)
+            .map(|idx| (ids.id(idx), positions[idx as usize]))
+            .filter(|&(_, position)| position != Position::UNKNOWN)
+            .collect_vec();
+
+        progress::status(format_args!(
+            "Pre computation done: {} nodes, {} ways ({}s)",
+            nodes.len(),
+            ways.len(),
+            timer.elapsed().as_secs_f64()
+        ));
+        Ok(Extract {
+            header,
+            nodes,
+            ways,
+            relations,
+        })
+    }
+
+    /// Applies `change`, re-evaluating the ways and relations it touches with
+    /// `profile`.
+    ///
+    /// Diffs only hold the nodes that changed, so ways that become routable
+    /// lack the positions of their other nodes unless the diff or another way
+    /// of the extract has them, and are split there like clipped ways.
+    pub fn apply(&mut self, change: &OsmChange, profile: &Profile) {
+        // Later changes of an object replace earlier ones, and `None` deletes
+        // it from the extract.
+        let mut nodes = BTreeMap::new();
+        let mut ways = BTreeMap::new();
+        let mut relations = BTreeMap::new();
+        for (action, osm_obj) in &change.changes {
+            let keep = *action != Action::Delete;
+            match osm_obj {
+                OsmObj::Node(node) => {
+                    nodes.insert(node.id, keep.then(|| (node.id, Position::of(node))));
+                }
+                OsmObj::Way(way) => {
+                    ways.insert(way.id, (keep && accepts(profile, way)).then(|| way.clone()));
+                }
+                OsmObj::Relation(relation) => {
+                    let restriction = profile.restriction(&relation.tags).is_some();
+                    relations.insert(relation.id, (keep && restriction).then(|| relation.clone()));
+                }
+            }
+        }
+        progress::status(format_args!(
+            "Changes applied: {} nodes, {} ways and {} relations",
+            nodes.len(),
+            ways.len(),
+            relations.len()
+        ));
+
+        self.ways = merge(std::mem::take(&mut self.ways), |way| way.id, ways);
+        self.relations = merge(
+            std::mem::take(&mut self.relations),
+            |relation| relation.id,
+            relations,
+        );
+        let needed = self
+            .ways
+            .iter()
+            .flat_map(|way| way.nodes.iter().copied())
+            .collect::<HashSet<_>>();
+        self.nodes = merge(std::mem::take(&mut self.nodes), |&(id, _)| id, nodes);
+        self.nodes.retain(|(id, _)| needed.contains(id));
+        self.header.revision += 1;
+    }
+
+    /// Where the extract of the graph cached for `profile_name` is saved.
+    pub fn path(cache_dir: &Path, profile_name: &str) -> PathBuf {
+        cache_dir.join(format!("graph-{}.extract", profile_name))
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(cache_dir)?;
+        let path = Self::path(cache_dir, &self.header.profile);
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        w.write_all(&[0; 4])?;
+        cache::write_header(&mut w, &self.header)?;
+
+        w.write_all(&(self.nodes.len() as u64).to_le_bytes())?;
+        w.write_all(&(self.ways.len() as u64).to_le_bytes())?;
+        w.write_all(&(self.relations.len() as u64).to_le_bytes())?;
+        for (id, _) in &self.nodes {
+            w.write_all(&id.0.to_le_bytes())?;
+        }
+        for (_, position) in &self.nodes {
+            w.write_all(&position.decimicro_lat.to_le_bytes())?;
+            w.write_all(&position.decimicro_lon.to_le_bytes())?;
+        }
+        for way in &self.ways {
+            w.write_all(&way.id.0.to_le_bytes())?;
+            w.write_all(&(way.nodes.len() as u32).to_le_bytes())?;
+            for id in &way.nodes {
+                w.write_all(&id.0.to_le_bytes())?;
+            }
+            write_tags(&mut w, &way.tags)?;
+        }
+        for relation in &self.relations {
+            w.write_all(&relation.id.0.to_le_bytes())?;
+            w.write_all(&(relation.refs.len() as u32).to_le_bytes())?;
+            for r in &relation.refs {
+                let (kind, id) = match r.member {
+                    OsmId::Node(id) => (0u8, id.0),
+                    OsmId::Way(id) => (1, id.0),
+                    OsmId::Relation(id) => (2, id.0),
+                };
+                w.write_all(&[kind])?;
+                w.write_all(&id.to_le_bytes())?;
+                cache::write_string(&mut w, &r.role)?;
+            }
+            write_tags(&mut w, &relation.tags)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Loads the extract saved for `graph`, failing if it was saved for
+    /// another graph.
+    pub fn load(cache_dir: &Path, graph: &RoadGraph) -> Result<Self, Error> {
+        let path = Self::path(cache_dir, &graph.header().profile);
+        Self::read(&path, graph.header()).map_err(|err| cache::read_error(&path, err))
+    }
+
+    fn read(path: &Path, graph_header: &CacheHeader) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(cache::invalid_data("not an extract".to_string()));
+        }
+        let mut version = [0; 8];
+        r.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version[..4].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(cache::invalid_data(format!(
+                "unsupported extract version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let header = cache::read_header(&mut r)?;
+        if &header != graph_header {
+            return Err(cache::invalid_data(
+                "saved for another graph, run build-graph again".to_string(),
+            ));
+        }
+
+        let node_count = cache::read_u64(&mut r)? as usize;
+        let way_count = cache::read_u64(&mut r)? as usize;
+        let relation_count = cache::read_u64(&mut r)? as usize;
+        let ids = cache::read_array(&mut r, node_count, i64::from_le_bytes)?;
+        let positions = cache::read_array(&mut r, node_count, |b: [u8; 8]| Position {
+            decimicro_lat: i32::from_le_bytes(b[..4].try_into().unwrap()),
+            decimicro_lon: i32::from_le_bytes(b[4..].try_into().unwrap()),
+        })?;
+        let nodes = ids.into_iter().map(NodeId).zip(positions).collect_vec();
+        let mut ways = Vec::new();
+        for _ in 0..way_count {
+            let id = WayId(i64::from_le_bytes(cache::read_bytes(&mut r)?));
+            let len = u32::from_le_bytes(cache::read_bytes(&mut r)?) as usize;
+            let nodes = cache::read_array(&mut r, len, |b| NodeId(i64::from_le_bytes(b)))?;
+            let tags = read_tags(&mut r)?;
+            ways.push(Way { id, tags, nodes });
+        }
+        let mut relations = Vec::new();
+        for _ in 0..relation_count {
+            let id = RelationId(i64::from_le_bytes(cache::read_bytes(&mut r)?));
+            let len = u32::from_le_bytes(cache::read_bytes(&mut r)?) as usize;
+            let mut refs = Vec::new();
+            for _ in 0..len {
+                let [kind] = cache::read_bytes(&mut r)?;
+                let id = i64::from_le_bytes(cache::read_bytes(&mut r)?);
+                let member = match kind {
+                    0 => OsmId::Node(NodeId(id)),
+                    1 => OsmId::Way(WayId(id)),
+                    2 => OsmId::Relation(RelationId(id)),
+                    _ => return Err(cache::invalid_data("unknown member type".to_string())),
+                };
+                let role = cache::read_string(&mut r)?.into();
+                refs.push(Ref { member, role });
+            }
+            let tags = read_tags(&mut r)?;
+            relations.push(Relation { id, tags, refs });
+        }
+
+        if !nodes.windows(2).all(|w| w[0].0 < w[1].0)
+            || !ways.windows(2).all(|w| w[0].id < w[1].id)
+            || !relations.windows(2).all(|w| w[0].id < w[1].id)
+        {
+            return Err(cache::invalid_data("objects are not sorted".to_string()));
+        }
+        Ok(Extract {
+            header,
+            nodes,
+            ways,
+            relations,
+        })
+    }
+}
+
+/// Whether the graph of `profile` has edges along `way`. Reversible ways can't
+/// be routed on at any given time.
+fn accepts(profile: &Profile, way: &Way) -> bool {
+    profile.accepts(&way.tags) && profile.direction(&way.tags) != Direction::Closed
+}
+
+/// Replaces, removes or adds the items of `items`, sorted by `key`, that
+/// `changes` has a key for.
+fn merge<K: Ord, T>(
+    items: Vec<T>,
+    key: impl Fn(&T) -> K,
+    changes: BTreeMap<K, Option<T>>,
+) -> Vec<T> {
+    items
+        .into_iter()
+        .merge_join_by(changes, |item, (k, _)| key(item).cmp(k))
+        .filter_map(|either| match either {
+            EitherOrBoth::Left(item) => Some(item),
+            EitherOrBoth::Right((_, change)) | EitherOrBoth::Both(_, (_, change)) => change,
+        })
+        .collect()
+}
+
+fn write_tags(w: &mut impl Write, tags: &Tags) -> io::Result<()> {
+    w.write_all(&(tags.len() as u32).to_le_bytes())?;
+    for (key, value) in tags.iter() {
+        cache::write_string(w, key)?;
+        cache::write_string(w, value)?;
+    }
+    Ok(())
+}
+
+fn read_tags(r: &mut impl Read) -> io::Result<Tags> {
+    let len = u32::from_le_bytes(cache::read_bytes(r)?);
+    (0..len)
+        .map(|_| Ok((cache::read_string(r)?.into(), cache::read_string(r)?.into())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::osmformat::{self, PrimitiveBlock, PrimitiveGroup};
+
+    use super::*;
+    use crate::{
+        fingerprint::tests::write_pbf,
+        graph::{AdjList, Nodes},
+    };
+
+    fn way(id: i64, highway: &str, nodes: &[i64]) -> Way {
+        Way {
+            id: WayId(id),
+            tags: [("highway".into(), highway.into())].into_iter().collect(),
+            nodes: nodes.iter().copied().map(NodeId).collect(),
+        }
+    }
+
+    fn position(id: i64) -> Position {
+        Position {
+            decimicro_lat: 356_800_000 + id as i32 * 1000,
+            decimicro_lon: 1_397_000_000,
+        }
+    }
+
+    /// An extract of `ways` with the positions of their nodes.
+    fn extract(ways: Vec<Way>) -> Extract {
+        let nodes = ways
+            .iter()
+            .flat_map(|way| way.nodes.iter().copied())
+            .sorted()
+            .dedup()
+            .map(|id| (id, position(id.0)))
+            .collect();
+        Extract {
+            header: RoadGraph::new(Nodes::new(), AdjList::new())
+                .header()
+                .clone(),
+            nodes,
+            ways,
+            relations: Vec::new(),
+        }
+    }
+
+    /// A data block of nodes at [`position`] and `ways` of tag and node lists.
+    fn primitive_block(nodes: &[i64], ways: &[(i64, (&str, &str), &[i64])]) -> PrimitiveBlock {
+        let mut strings = vec![Vec::new()];
+        let mut group = PrimitiveGroup::new();
+        for &id in nodes {
+            let mut node = osmformat::Node::new();
+            node.set_id(id);
+            node.set_lat(position(id).decimicro_lat as i64);
+            node.set_lon(position(id).decimicro_lon as i64);
+            group.mut_nodes().push(node);
+        }
+        for &(id, (key, value), refs) in ways {
+            let mut way = osmformat::Way::new();
+            way.set_id(id);
+            strings.extend([key, value].map(|s| s.as_bytes().to_vec()));
+            way.set_keys(vec![strings.len() as u32 - 2]);
+            way.set_vals(vec![strings.len() as u32 - 1]);
+            way.set_refs(
+                refs.iter()
+                    .scan(0, |prev, &id| Some(id - std::mem::replace(prev, id)))
+                    .collect(),
+            );
+            group.mut_ways().push(way);
+        }
+        let mut block = PrimitiveBlock::new();
+        block.mut_stringtable().set_s(strings.into());
+        block.mut_primitivegroup().push(group);
+        block
+    }
+
+    #[test]
+    fn only_nodes_of_routable_ways_are_read() {
+        let dir = std::env::temp_dir().join(format!("read-osm-extract-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pbf = dir.join("test.osm.pbf");
+        // A road, a building and a motorway, which bicycles can't take.
+        let block = primitive_block(
+            &[1, 2, 3, 4, 5, 6],
+            &[
+                (10, ("highway", "residential"), &[1, 2]),
+                (11, ("building", "yes"), &[3, 4, 3]),
+                (12, ("highway", "motorway"), &[2, 5]),
+            ],
+        );
+        write_pbf(&pbf, 0, &[block]);
+        let extract = Extract::from_pbf(&pbf, &Profile::bicycle(), None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let extract = extract.unwrap();
+        assert_eq!(extract.ways.iter().map(|way| way.id.0).collect_vec(), [10]);
+        assert_eq!(
+            extract.nodes,
+            [(NodeId(1), position(1)), (NodeId(2), position(2))]
+        );
+    }
+
+    #[test]
+    fn changes_are_applied_to_the_extract() {
+        let mut profile = Profile::bicycle();
+        profile.min_component_size = 0;
+        let mut extract = extract(vec![
+            way(10, "residential", &[1, 2]),
+            way(11, "residential", &[2, 3]),
+        ]);
+        // 11 becomes a motorway, and 12 leads from 1 to a new node 4.
+        let change = OsmChange::read(
+            r#"<osmChange>
+  <modify>
+    <way id="11"><nd ref="2"/><nd ref="3"/><tag k="highway" v="motorway"/></way>
+    <node id="1" lat="35.679" lon="139.7"/>
+  </modify>
+  <create>
+    <node id="4" lat="35.681" lon="139.7"/>
+    <node id="5" lat="35.682" lon="139.7"/>
+    <way id="12"><nd ref="1"/><nd ref="4"/><tag k="highway" v="cycleway"/></way>
+  </create>
+</osmChange>"#
+                .as_bytes(),
+        )
+        .unwrap();
+
+        extract.apply(&change, &profile);
+        assert_eq!(extract.header.revision, 1);
+        assert_eq!(
+            extract.ways.iter().map(|way| way.id.0).collect_vec(),
+            [10, 12]
+        );
+        // 3 is only on the motorway now, and 5 isn't on any way.
+        assert_eq!(
+            extract.nodes.iter().map(|(id, _)| id.0).collect_vec(),
+            [1, 2, 4]
+        );
+        let graph = RoadGraph::from_extract(&extract, &profile, None).unwrap();
+        let idx = |id| graph.index_of(NodeId(id)).unwrap();
+        assert!(!graph.contains(NodeId(3)));
+        assert_eq!(graph.position(idx(1)).unwrap().decimicro_lat, 356_790_000);
+        assert!(graph.edges(idx(2)).iter().any(|edge| edge.target == idx(4)));
+    }
+
+    #[test]
+    fn ways_made_routable_take_positions_from_the_diff() {
+        let mut profile = Profile::bicycle();
+        profile.min_component_size = 0;
+        let mut extract = extract(vec![way(10, "residential", &[1, 2])]);
+        // 11 was a motorway, so the extract lacks 3 and 4, and the diff only
+        // moves 3.
+        let change = OsmChange::read(
+            r#"<osmChange>
+  <modify>
+    <way id="11"><nd ref="2"/><nd ref="3"/><nd ref="4"/><tag k="highway" v="residential"/></way>
+    <node id="3" lat="35.683" lon="139.7"/>
+  </modify>
+</osmChange>"#
+                .as_bytes(),
+        )
+        .unwrap();
+
+        extract.apply(&change, &profile);
+        assert_eq!(
+            extract.nodes.iter().map(|(id, _)| id.0).collect_vec(),
+            [1, 2, 3]
+        );
+        let graph = RoadGraph::from_extract(&extract, &profile, None).unwrap();
+        let idx = |id| graph.index_of(NodeId(id)).unwrap();
+        assert!(!graph.contains(NodeId(4)));
+        assert_eq!(graph.position(idx(3)).unwrap().decimicro_lat, 356_830_000);
+        assert!(graph.edges(idx(1)).iter().any(|edge| edge.target == idx(3)));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use flate2::read::ZlibDecoder;
+use osmpbfreader::{
+    fileformat::{Blob, BlobHeader},
+    osmformat::HeaderBlock,
+};
+use protobuf::Message;
+
+/// Largest `BlobHeader` and `Blob` the PBF format allows.
+const MAX_BLOB_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+/// Identifies a PBF file well enough to tell when it has been replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PbfFingerprint {
+    pub size: u64,
+    /// Modification time in seconds since the UNIX epoch.
+    pub modified: i64,
+    /// `osmosis_replication_timestamp` of the PBF header, or 0 if missing.
+    pub replication_timestamp: i64,
+}
+
+impl PbfFingerprint {
+    pub fn of(pbf_path: &Path) -> io::Result<Self> {
+        let metadata = pbf_path.metadata()?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let header = read_header_block(&mut BufReader::new(File::open(pbf_path)?))?;
+        Ok(PbfFingerprint {
+            size: metadata.len(),
+            modified,
+            replication_timestamp: header.get_osmosis_replication_timestamp(),
+        })
+    }
+}
+
+/// Reads the `OSMHeader` block, which is always the first block of a PBF.
+pub fn read_header_block(r: &mut impl Read) -> io::Result<HeaderBlock> {
+    let mut len = [0; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    let blob_header: BlobHeader = parse(&read_exact_vec(r, len, MAX_BLOB_HEADER_SIZE)?)?;
+    if blob_header.get_field_type() != "OSMHeader" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "PBF doesn't start with an OSMHeader block",
+        ));
+    }
+    let datasize = usize::try_from(blob_header.get_datasize()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("negative PBF blob size {}", blob_header.get_datasize()),
+        )
+    })?;
+    let blob: Blob = parse(&read_exact_vec(r, datasize, MAX_BLOB_SIZE)?)?;
+
+    if blob.has_raw() {
+        parse(blob.get_raw())
+    } else if blob.has_zlib_data() {
+        let mut data = Vec::new();
+        ZlibDecoder::new(blob.get_zlib_data()).read_to_end(&mut data)?;
+        parse(&data)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported PBF header compression",
+        ))
+    }
+}
+
+/// Reads `len` bytes, failing before allocating them if `len` is above `max`,
+/// as when the file isn't a PBF.
+fn read_exact_vec(r: &mut impl Read, len: usize, max: usize) -> io::Result<Vec<u8>> {
+    if len > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("PBF block of {} bytes is larger than {}", len, max),
+        ));
+    }
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn parse<M: Message>(bytes: &[u8]) -> io::Result<M> {
+    M::parse_from_bytes(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across builds.
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use osmpbfreader::osmformat::PrimitiveBlock;
+
+    use super::*;
+
+    /// Writes a PBF of a header block with `replication_timestamp` followed by
+    /// the `data` blocks.
+    pub(crate) fn write_pbf(path: &Path, replication_timestamp: i64, data: &[PrimitiveBlock]) {
+        let mut header = HeaderBlock::new();
+        header.set_osmosis_replication_timestamp(replication_timestamp);
+        let blocks = [("OSMHeader", header.write_to_bytes().unwrap())]
+            .into_iter()
+            .chain(
+                data.iter()
+                    .map(|block| ("OSMData", block.write_to_bytes().unwrap())),
+            );
+        let mut bytes = Vec::new();
+        for (field_type, raw) in blocks {
+            let mut blob = Blob::new();
+            blob.set_raw(raw);
+            let blob = blob.write_to_bytes().unwrap();
+            let mut blob_header = BlobHeader::new();
+            blob_header.set_field_type(field_type.to_string());
+            blob_header.set_datasize(blob.len() as i32);
+            let blob_header = blob_header.write_to_bytes().unwrap();
+            bytes.extend((blob_header.len() as u32).to_be_bytes());
+            bytes.extend(blob_header);
+            bytes.extend(blob);
+        }
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn oversized_and_negative_blocks_are_rejected() {
+        let read = |bytes: &[u8]| read_header_block(&mut &bytes[..]).unwrap_err().kind();
+
+        // An HTML page saved as the PBF claims a BlobHeader of about 1 GB.
+        assert_eq!(
+            read(b"<html><body>Not found</body></html>"),
+            io::ErrorKind::InvalidData
+        );
+        for datasize in [-1, MAX_BLOB_SIZE as i32 + 1] {
+            let mut blob_header = BlobHeader::new();
+            blob_header.set_field_type("OSMHeader".to_string());
+            blob_header.set_datasize(datasize);
+            let blob_header = blob_header.write_to_bytes().unwrap();
+            let mut bytes = (blob_header.len() as u32).to_be_bytes().to_vec();
+            bytes.extend(blob_header);
+            assert_eq!(read(&bytes), io::ErrorKind::InvalidData);
+        }
+    }
+}
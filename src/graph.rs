@@ -0,0 +1,1492 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use geo::{point, HaversineDistance};
+use itertools::Itertools;
+use osmpbfreader::{Node, NodeId, Way};
+
+use crate::{
+    cache::{self, CacheHeader, CsrGraph},
+    components::Components,
+    elevation::{self, Dem},
+    error::Error,
+    extract::Extract,
+    fingerprint::{self, PbfFingerprint},
+    profile::{Direction, Profile, TurnCosts},
+    progress,
+    restriction::{TurnRestriction, TurnRestrictions},
+};
+
+/// Dense index of a node of a [`RoadGraph`], which routers work with. See
+/// [`NodeIds`] for the OSM IDs they stand for.
+pub type NodeIdx = u32;
+
+/// Nodes by OSM ID, to build a graph from with [`RoadGraph::new`].
+pub type Nodes = HashMap<NodeId, Node>;
+/// Edges by the OSM ID of their source, to build a graph from with
+/// [`RoadGraph::new`].
+pub type AdjList = HashMap<NodeId, Vec<Edge<NodeId>>>;
+
+/// A directed edge of the road graph, leading to a node index unless given by
+/// OSM ID in an [`AdjList`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge<N = NodeIdx> {
+    pub target: N,
+    /// Length in meters.
+    pub length: f64,
+    /// Weight minimized by the routers, see [`Profile::edge_cost`].
+    pub cost: f64,
+    /// Whether the edge is on a major road, see [`TurnCosts`].
+    pub major: bool,
+}
+
+/// A node an edge passes through after [collapsing a
+/// chain](RoadGraph::collapse_chains), with the length and cost of the edge up
+/// to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Via {
+    pub node: NodeIdx,
+    /// Length in meters from the source of the edge.
+    pub length: f64,
+    /// Cost from the source of the edge, before any turn cost of passing the node.
+    pub cost: f64,
+}
+
+/// Coordinates of a node in decimicro degrees, as stored in the PBF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub decimicro_lat: i32,
+    pub decimicro_lon: i32,
+}
+
+impl Position {
+    /// Stands for the position of nodes only known as ends of edges.
+    pub(crate) const UNKNOWN: Position = Position {
+        decimicro_lat: i32::MIN,
+        decimicro_lon: i32::MIN,
+    };
+
+    pub fn of(node: &Node) -> Self {
+        Position {
+            decimicro_lat: node.decimicro_lat,
+            decimicro_lon: node.decimicro_lon,
+        }
+    }
+
+    /// Latitude in degrees.
+    pub fn lat(&self) -> f64 {
+        self.decimicro_lat as f64 * 1e-7
+    }
+
+    /// Longitude in degrees.
+    pub fn lon(&self) -> f64 {
+        self.decimicro_lon as f64 * 1e-7
+    }
+}
+
+/// OSM IDs of the nodes of a graph by index, and indices by OSM ID.
+#[derive(Debug, Clone, Default)]
+pub struct NodeIds {
+    ids: Vec<NodeId>,
+    indices: HashMap<NodeId, NodeIdx>,
+}
+
+impl NodeIds {
+    /// Numbers `ids` in the order given.
+    pub fn new(ids: Vec<NodeId>) -> Self {
+        let indices = ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (id, i as NodeIdx))
+            .collect();
+        NodeIds { ids, indices }
+    }
+
+    pub fn index(&self, id: NodeId) -> Option<NodeIdx> {
+        self.indices.get(&id).copied()
+    }
+
+    pub fn id(&self, idx: NodeIdx) -> NodeId {
+        self.ids[idx as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Result of comparing a cached graph with the PBF and profile it would be
+/// built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheStatus {
+    Missing,
+    Fresh,
+    /// The cache matches the profile, but the PBF to compare it against is
+    /// missing.
+    PbfMissing,
+    /// The cache must be rebuilt, for the given reason.
+    Stale(String),
+}
+
+/// Directed graph of the roads accepted by a [`Profile`].
+///
+/// Nodes are numbered densely, in ascending order of their OSM IDs when the
+/// graph is built, and everything about them is stored by [`NodeIdx`].
+pub struct RoadGraph {
+    ids: NodeIds,
+    /// Unknown for nodes only given as ends of edges.
+    positions: Vec<Position>,
+    adj_list: Vec<Vec<Edge>>,
+    /// Incoming edges by node, whose `target` is the node they leave.
+    reverse_adj_list: Vec<Vec<Edge>>,
+    /// Node elevations in meters, NaN if unknown and empty unless built with
+    /// a DEM.
+    elevations: Vec<f64>,
+    restrictions: Vec<TurnRestriction>,
+    turns: TurnRestrictions,
+    turn_costs: Option<TurnCosts>,
+    /// Whether each node has a major edge, where crossing is penalized.
+    major_nodes: Vec<bool>,
+    /// Nodes passed by the edge between a `(source, target)` pair, for edges
+    /// collapsing a chain.
+    geometries: HashMap<(NodeIdx, NodeIdx), Vec<Via>>,
+    min_cost_per_meter: f64,
+    header: CacheHeader,
+}
+
+impl RoadGraph {
+    /// Builds a graph from nodes and edges given by OSM ID. Nodes only found
+    /// in `adj_list` have no position.
+    pub fn new(nodes: Nodes, adj_list: AdjList) -> Self {
+        let ids = NodeIds::new(
+            nodes
+                .keys()
+                .copied()
+                .chain(
+                    adj_list
+                        .iter()
+                        .flat_map(|(&id, edges)| edges.iter().map(|edge| edge.target).chain([id])),
+                )
+                .sorted()
+                .dedup()
+                .collect(),
+        );
+        let positions = ids
+            .ids
+            .iter()
+            .map(|id| nodes.get(id).map_or(Position::UNKNOWN, Position::of))
+            .collect();
+        let mut dense = vec![Vec::new(); ids.len()];
+        for (id, edges) in adj_list {
+            dense[ids.index(id).unwrap() as usize] = edges
+                .into_iter()
+                .map(|edge| Edge {
+                    target: ids.index(edge.target).unwrap(),
+                    length: edge.length,
+                    cost: edge.cost,
+                    major: edge.major,
+                })
+                .collect();
+        }
+        Self::from_parts(
+            ids,
+            positions,
+            dense,
+            Vec::new(),
+            HashMap::new(),
+            CacheHeader {
+                version: cache::FORMAT_VERSION,
+                source_pbf: String::new(),
+                pbf: PbfFingerprint::default(),
+                profile: String::new(),
+                profile_hash: 0,
+                dem: String::new(),
+                replication_sequence: 0,
+                revision: 0,
+            },
+        )
+    }
+
+    fn from_parts(
+        ids: NodeIds,
+        positions: Vec<Position>,
+        adj_list: Vec<Vec<Edge>>,
+        elevations: Vec<f64>,
+        geometries: HashMap<(NodeIdx, NodeIdx), Vec<Via>>,
+        header: CacheHeader,
+    ) -> Self {
+        RoadGraph {
+            ids,
+            positions,
+            reverse_adj_list: reverse(&adj_list),
+            major_nodes: major_nodes(&adj_list),
+            adj_list,
+            elevations,
+            restrictions: Vec::new(),
+            turns: TurnRestrictions::default(),
+            turn_costs: None,
+            geometries,
+            min_cost_per_meter: 0.0,
+            header,
+        }
+    }
+
+    /// Replaces the turn restrictions of the graph, leaving out those through
+    /// nodes it doesn't have.
+    pub fn with_restrictions(mut self, restrictions: Vec<TurnRestriction>) -> Self {
+        let restrictions = restrictions
+            .into_iter()
+            .filter(|restriction| restriction.nodes.iter().all(|&id| self.contains(id)))
+            .collect_vec();
+        self.turns = TurnRestrictions::new(&restrictions, |id| self.ids.index(id));
+        self.restrictions = restrictions;
+        self
+    }
+
+    /// Sets the lower bound of the cost per meter of the edges, which is zero
+    /// unless given.
+    pub fn with_min_cost_per_meter(mut self, min_cost_per_meter: f64) -> Self {
+        self.min_cost_per_meter = min_cost_per_meter;
+        self
+    }
+
+    /// Replaces the turn costs of the graph, routing edge by edge if given.
+    pub fn with_turn_costs(mut self, turn_costs: Option<TurnCosts>) -> Self {
+        self.major_nodes = major_nodes(&self.adj_list);
+        self.turn_costs = turn_costs;
+        self
+    }
+
+    /// Replaces every chain of nodes that only lead from one neighbor to the
+    /// other by a single edge between the junctions at its ends, passing
+    /// through the chain's nodes as [`Via`]s.
+    ///
+    /// Nodes of turn restrictions are kept, and so is a node of any chain
+    /// that would otherwise end up parallel to another edge, as routers tell
+    /// edges apart by their ends. Turn costs of passing the collapsed nodes
+    /// are added to the edge costs, so routes cost the same, but they can only
+    /// turn around at junctions.
+    pub fn collapse_chains(mut self) -> Self {
+        let restricted = self
+            .restrictions
+            .iter()
+            .flat_map(|restriction| restriction.nodes.iter())
+            .filter_map(|&id| self.ids.index(id))
+            .collect::<HashSet<_>>();
+        let mut interior = (0..self.node_count() as NodeIdx)
+            .filter(|idx| !restricted.contains(idx) && self.chain_kind(*idx).is_some())
+            .collect::<HashSet<_>>();
+
+        let chains = loop {
+            let chains = self.chains(&interior);
+            let mut links = HashMap::<(NodeIdx, NodeIdx), usize>::new();
+            for (source, path) in &chains {
+                let target = *path.last().unwrap();
+                *links
+                    .entry((*source.min(&target), *source.max(&target)))
+                    .or_default() += 1;
+            }
+            let mut kept = Vec::new();
+            for (source, path) in &chains {
+                let target = *path.last().unwrap();
+                let both_ways = self.chain_kind(path[0]) == Some(true);
+                let parallel = source == &target
+                    || links[&(*source.min(&target), *source.max(&target))]
+                        > 1 + both_ways as usize
+                    || self.edges(*source).iter().any(|edge| edge.target == target)
+                    || self.edges(target).iter().any(|edge| edge.target == *source);
+                if parallel {
+                    // Any node of the chain would do, the smallest one is
+                    // kept so that both directions agree.
+                    kept.extend(path[..path.len() - 1].iter().min());
+                }
+            }
+            if kept.is_empty() {
+                break chains;
+            }
+            for idx in kept {
+                interior.remove(&idx);
+            }
+        };
+
+        let mut collapsed = HashSet::<NodeIdx>::new();
+        for (source, path) in chains {
+            collapsed.extend(&path[..path.len() - 1]);
+            let mut vias = Vec::with_capacity(path.len() - 1);
+            let (mut length, mut cost) = (0.0, 0.0);
+            let mut major = false;
+            let mut prev = source;
+            for (i, &idx) in path.iter().enumerate() {
+                let edge = self.edges(prev).iter().find(|e| e.target == idx).unwrap();
+                length += edge.length;
+                cost += edge.cost;
+                major = edge.major;
+                if let Some(&next) = path.get(i + 1) {
+                    vias.push(Via {
+                        node: idx,
+                        length,
+                        cost,
+                    });
+                    cost += self.pass_cost(prev, idx, next);
+                }
+                prev = idx;
+            }
+            let edge = Edge {
+                target: prev,
+                length,
+                cost,
+                major,
+            };
+            let edges = &mut self.adj_list[source as usize];
+            let first = edges.iter().position(|e| e.target == path[0]).unwrap();
+            edges[first] = edge;
+            self.geometries.insert((source, prev), vias);
+        }
+        for idx in collapsed {
+            self.adj_list[idx as usize] = Vec::new();
+        }
+        self.reverse_adj_list = reverse(&self.adj_list);
+        self.major_nodes = major_nodes(&self.adj_list);
+        self
+    }
+
+    /// Removes the nodes of the strongly connected [`Components`] with fewer
+    /// than `min_size` nodes, along with their edges and turn restrictions.
+    pub fn drop_small_components(mut self, min_size: usize) -> Self {
+        let components = Components::new(&self);
+        let dropped = |idx: NodeIdx| {
+            components
+                .of(idx)
+                .is_some_and(|c| components.sizes()[c] < min_size)
+        };
+        for (idx, edges) in self.adj_list.iter_mut().enumerate() {
+            if dropped(idx as NodeIdx) {
+                edges.clear();
+            }
+            edges.retain(|edge| !dropped(edge.target));
+        }
+        let adj_list = &self.adj_list;
+        self.geometries.retain(|&(source, target), _| {
+            adj_list[source as usize]
+                .iter()
+                .any(|edge| edge.target == target)
+        });
+
+        let mut kept = vec![false; self.node_count()];
+        for (source, edges) in self.adj_list.iter().enumerate() {
+            for edge in edges {
+                kept[source] = true;
+                kept[edge.target as usize] = true;
+            }
+        }
+        for via in self.geometries.values().flatten() {
+            kept[via.node as usize] = true;
+        }
+        self.retain_nodes(&kept)
+    }
+
+    /// Removes the nodes not `kept`, which must have no edges left, and
+    /// numbers the others densely again.
+    fn retain_nodes(self, kept: &[bool]) -> Self {
+        let mut new_index = vec![NodeIdx::MAX; kept.len()];
+        let mut ids = Vec::new();
+        for (idx, _) in kept.iter().enumerate().filter(|(_, &kept)| kept) {
+            new_index[idx] = ids.len() as NodeIdx;
+            ids.push(self.ids.ids[idx]);
+        }
+        let renumber = |idx: NodeIdx| new_index[idx as usize];
+
+        let adj_list = retain_kept(self.adj_list, kept)
+            .into_iter()
+            .map(|edges: Vec<Edge>| {
+                edges
+                    .into_iter()
+                    .map(|edge| Edge {
+                        target: renumber(edge.target),
+                        ..edge
+                    })
+                    .collect()
+            })
+            .collect();
+        let geometries = self
+            .geometries
+            .into_iter()
+            .map(|((source, target), vias)| {
+                let vias = vias
+                    .into_iter()
+                    .map(|via| Via {
+                        node: renumber(via.node),
+                        ..via
+                    })
+                    .collect();
+                ((renumber(source), renumber(target)), vias)
+            })
+            .collect();
+        let elevations = if self.elevations.is_empty() {
+            Vec::new()
+        } else {
+            retain_kept(self.elevations, kept)
+        };
+
+        RoadGraph {
+            turn_costs: self.turn_costs,
+            min_cost_per_meter: self.min_cost_per_meter,
+            ..Self::from_parts(
+                NodeIds::new(ids),
+                retain_kept(self.positions, kept),
+                adj_list,
+                elevations,
+                geometries,
+                self.header,
+            )
+        }
+        .with_restrictions(self.restrictions)
+    }
+
+    /// Makes `idx` a junction again if it was collapsed into an edge by
+    /// [`RoadGraph::collapse_chains`], so that routes can start or end at it.
+    pub fn split_chains_at(&mut self, idx: NodeIdx) {
+        let chains = self
+            .geometries
+            .iter()
+            .filter_map(|(&ends, vias)| Some((ends, vias.iter().position(|via| via.node == idx)?)))
+            .collect_vec();
+        for &((source, target), i) in &chains {
+            let vias = self.geometries.remove(&(source, target)).unwrap();
+            let via = vias[i];
+            let before = if i == 0 { source } else { vias[i - 1].node };
+            let after = vias.get(i + 1).map_or(target, |via| via.node);
+            let passed = via.cost + self.pass_cost(before, idx, after);
+
+            let edge = self.adj_list[source as usize]
+                .iter_mut()
+                .find(|e| e.target == target)
+                .unwrap();
+            let rest = Edge {
+                length: edge.length - via.length,
+                cost: edge.cost - passed,
+                ..*edge
+            };
+            *edge = Edge {
+                target: idx,
+                length: via.length,
+                cost: via.cost,
+                ..*edge
+            };
+            self.adj_list[idx as usize].push(rest);
+
+            if i > 0 {
+                self.geometries.insert((source, idx), vias[..i].to_vec());
+            }
+            if i + 1 < vias.len() {
+                let rest = vias[i + 1..]
+                    .iter()
+                    .map(|v| Via {
+                        node: v.node,
+                        length: v.length - via.length,
+                        cost: v.cost - passed,
+                    })
+                    .collect();
+                self.geometries.insert((idx, target), rest);
+            }
+        }
+        if !chains.is_empty() {
+            self.reverse_adj_list = reverse(&self.adj_list);
+            self.major_nodes = major_nodes(&self.adj_list);
+        }
+    }
+
+    /// Whether `idx` only leads from one neighbor to the other, and if so
+    /// whether in both directions. The edges on both sides must agree on
+    /// being major so that the collapsed edge does too.
+    fn chain_kind(&self, idx: NodeIdx) -> Option<bool> {
+        let (out, inc) = (self.edges(idx), self.incoming_edges(idx));
+        let major = out.first()?.major;
+        if out
+            .iter()
+            .chain(inc)
+            .any(|edge| edge.target == idx || edge.major != major)
+        {
+            return None;
+        }
+        match (out, inc) {
+            ([a], [b]) if a.target != b.target => Some(false),
+            ([a, b], [c, d])
+                if a.target != b.target
+                    && ((a.target, b.target) == (c.target, d.target)
+                        || (a.target, b.target) == (d.target, c.target)) =>
+            {
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    /// Paths from a junction through `interior` nodes up to the next
+    /// junction, by the junction they start from.
+    fn chains(&self, interior: &HashSet<NodeIdx>) -> Vec<(NodeIdx, Vec<NodeIdx>)> {
+        let mut chains = Vec::new();
+        for source in self.sources() {
+            if interior.contains(&source) {
+                continue;
+            }
+            for edge in self.edges(source) {
+                let (mut prev, mut cur) = (source, edge.target);
+                let mut path = Vec::new();
+                while interior.contains(&cur) {
+                    path.push(cur);
+                    let next = self.edges(cur).iter().find(|e| e.target != prev).unwrap();
+                    (prev, cur) = (cur, next.target);
+                }
+                if !path.is_empty() {
+                    path.push(cur);
+                    chains.push((source, path));
+                }
+            }
+        }
+        chains
+    }
+
+    /// Turn cost of passing through `via` from `from` to `to` on a chain.
+    fn pass_cost(&self, from: NodeIdx, via: NodeIdx, to: NodeIdx) -> f64 {
+        match (
+            &self.turn_costs,
+            self.position(from),
+            self.position(via),
+            self.position(to),
+        ) {
+            (Some(turn_costs), Some(a), Some(b), Some(c)) => {
+                turn_costs.cost(turn_angle(a, b, c), false, false)
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Builds the graph from the ways of a PBF extract accepted by `profile`,
+    /// taking node elevations from the HGT tiles in `dem_dir` if given.
+    pub fn from_pbf(
+        pbf_path: &Path,
+        profile: &Profile,
+        dem_dir: Option<&Path>,
+    ) -> Result<Self, Error> {
+        Self::from_extract(
+            &Extract::from_pbf(pbf_path, profile, dem_dir)?,
+            profile,
+            dem_dir,
+        )
+    }
+
+    /// Builds the graph from the routable part of an extract read for
+    /// `profile`, taking node elevations from the HGT tiles in `dem_dir` if
+    /// given.
+    pub fn from_extract(
+        extract: &Extract,
+        profile: &Profile,
+        dem_dir: Option<&Path>,
+    ) -> Result<Self, Error> {
+        let mut dem = dem_dir.map(Dem::new).transpose()?;
+        let ways = &extract.ways;
+        let mut needed = ways
+            .iter()
+            .flat_map(|way| way.nodes.iter().copied())
+            .collect_vec();
+        needed.sort_unstable();
+        needed.dedup();
+        let ids = NodeIds::new(needed);
+        let mut positions = vec![Position::UNKNOWN; ids.len()];
+        for &(id, position) in &extract.nodes {
+            if let Some(idx) = ids.index(id) {
+                positions[idx as usize] = position;
+            }
+        }
+        let present =
+            |id: &NodeId| positions[ids.index(*id).unwrap() as usize] != Position::UNKNOWN;
+
+        // Extracts clipped at a border keep the ways crossing it but not their
+        // nodes outside, so such ways only keep their runs of present nodes.
+        let mut split_ways = 0;
+        let mut dropped_ways = 0;
+        let mut missing_nodes = 0;
+        let mut pieces = Vec::new();
+        for way in ways {
+            let missing = way.nodes.iter().filter(|id| !present(id)).count();
+            if missing == 0 {
+                continue;
+            }
+            missing_nodes += missing;
+            let way_pieces = split_at_missing_nodes(way, present);
+            if way_pieces.is_empty() {
+                dropped_ways += 1;
+            } else {
+                split_ways += 1;
+            }
+            pieces.extend(way_pieces);
+        }
+        if missing_nodes > 0 {
+            progress::status(format_args!(
+                "Ways with missing nodes: {} split into {} pieces, {} dropped ({} nodes missing)",
+                split_ways,
+                pieces.len(),
+                dropped_ways,
+                missing_nodes
+            ));
+        }
+        let complete = ways
+            .iter()
+            .filter(|way| way.nodes.iter().all(present))
+            .chain(&pieces)
+            .filter(|way| way.nodes.len() > 1)
+            .collect_vec();
+
+        let mut used = vec![false; ids.len()];
+        for way in &complete {
+            for &id in &way.nodes {
+                used[ids.index(id).unwrap() as usize] = true;
+            }
+        }
+        let mut elevations = Vec::new();
+        if let Some(dem) = &mut dem {
+            elevations.reserve(positions.len());
+            for (position, &used) in positions.iter().zip(&used) {
+                let elevation = if used {
+                    dem.elevation(position.lat(), position.lon())?
+                } else {
+                    None
+                };
+                elevations.push(elevation.unwrap_or(f64::NAN));
+            }
+        }
+        let mut adj_list = vec![Vec::new(); ids.len()];
+        let bar = progress::items("Adding edges", complete.len());
+        for way in bar.wrap_iter(complete.iter()) {
+            add_way(&mut adj_list, way, &ids, &positions, &elevations, profile);
+        }
+        bar.finish_and_clear();
+
+        let ways_by_id = ways
+            .iter()
+            .map(|way| (way.id, way))
+            .collect::<HashMap<_, _>>();
+        let relations = extract
+            .relations
+            .iter()
+            .filter_map(|relation| Some((relation, profile.restriction(&relation.tags)?)))
+            .collect_vec();
+        let mut restrictions = Vec::new();
+        let mut invalid_restrictions = 0;
+        for &(relation, kind) in &relations {
+            match TurnRestriction::resolve(relation, kind, &ways_by_id) {
+                Ok(Some(restriction))
+                    if restriction
+                        .nodes
+                        .iter()
+                        .all(|id| ids.index(*id).is_some_and(|idx| used[idx as usize])) =>
+                {
+                    restrictions.push(restriction)
+                }
+                Ok(_) => {}
+                Err(_) => invalid_restrictions += 1,
+            }
+        }
+        if !relations.is_empty() {
+            progress::status(format_args!(
+                "Turn restrictions: {} applied, {} invalid",
+                restrictions.len(),
+                invalid_restrictions
+            ));
+        }
+
+        if dem.is_some() {
+            progress::status(format_args!(
+                "Elevations found for {} of {} nodes",
+                elevations.iter().filter(|e| !e.is_nan()).count(),
+                used.iter().filter(|&&used| used).count()
+            ));
+        }
+
+        let graph = Self::from_parts(
+            ids,
+            positions,
+            adj_list,
+            elevations,
+            HashMap::new(),
+            extract.header.clone(),
+        )
+        .retain_nodes(&used)
+        .with_restrictions(restrictions)
+        .with_turn_costs(profile.turn_costs.clone())
+        .with_min_cost_per_meter(profile.min_cost_per_meter())
+        .collapse_chains();
+        let components = Components::new(&graph);
+        let sizes = components.sizes();
+        progress::status(format_args!(
+            "Components: {} strongly connected, the largest with {} of {} nodes",
+            sizes.len(),
+            sizes.first().copied().unwrap_or(0),
+            graph.node_count()
+        ));
+        let small = sizes
+            .iter()
+            .filter(|&&size| size < profile.min_component_size)
+            .collect_vec();
+        let graph = if small.is_empty() {
+            graph
+        } else {
+            progress::status(format_args!(
+                "Dropping {} components of fewer than {} nodes, {} nodes in total",
+                small.len(),
+                profile.min_component_size,
+                small.into_iter().sum::<usize>()
+            ));
+            graph.drop_small_components(profile.min_component_size)
+        };
+        let collapsed = graph
+            .geometries
+            .values()
+            .flatten()
+            .map(|via| via.node)
+            .collect::<HashSet<_>>()
+            .len();
+        progress::status(format_args!(
+            "Chains collapsed: {} of {} nodes are now inside edges",
+            collapsed,
+            graph.node_count()
+        ));
+        if let Some(peak) = peak_memory() {
+            progress::status(format_args!(
+                "Peak memory: {:.0} MiB",
+                peak as f64 / (1 << 20) as f64
+            ));
+        }
+        Ok(graph)
+    }
+
+    /// Where the graph built with the profile named `profile_name` is cached, so
+    /// that graphs of several profiles can share `cache_dir`.
+    pub fn cache_path(cache_dir: &Path, profile_name: &str) -> PathBuf {
+        cache_dir.join(format!("graph-{}.bin", profile_name))
+    }
+
+    /// Checks whether the graph cached in `cache_dir` was built from the current
+    /// `pbf_path` with the current `profile` and cache format.
+    pub fn check_cache(
+        cache_dir: &Path,
+        pbf_path: &Path,
+        profile: &Profile,
+        dem_dir: Option<&Path>,
+    ) -> CacheStatus {
+        let cached = match CacheHeader::read(&Self::cache_path(cache_dir, &profile.name)) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return CacheStatus::Missing,
+            Err(err) => return CacheStatus::Stale(format!("unreadable cache: {}", err)),
+        };
+
+        if cached.profile != profile.name || cached.profile_hash != profile.fingerprint() {
+            return CacheStatus::Stale(format!(
+                "built with profile {} ({:016x}), current is {} ({:016x})",
+                cached.profile,
+                cached.profile_hash,
+                profile.name,
+                profile.fingerprint()
+            ));
+        }
+        let dem = dem_name(dem_dir);
+        if cached.dem != dem {
+            return CacheStatus::Stale(format!(
+                "built with DEM {:?}, current is {:?}",
+                cached.dem, dem
+            ));
+        }
+        if !pbf_path.exists() {
+            return CacheStatus::PbfMissing;
+        }
+        match expected_header(pbf_path, profile, dem_dir) {
+            Ok(expected) if expected.source_pbf != cached.source_pbf => {
+                CacheStatus::Stale(format!(
+                    "built from {}, not {}",
+                    cached.source_pbf, expected.source_pbf
+                ))
+            }
+            Ok(expected) if expected.pbf != cached.pbf => CacheStatus::Stale(format!(
+                "{} has changed since the cache was built",
+                expected.source_pbf
+            )),
+            Ok(_) => CacheStatus::Fresh,
+            Err(err) => CacheStatus::Stale(format!("unreadable PBF: {}", err)),
+        }
+    }
+
+    /// Loads a graph previously written by [`RoadGraph::save`].
+    pub fn load(cache_dir: &Path, profile: &Profile) -> Result<Self, Error> {
+        let path = Self::cache_path(cache_dir, &profile.name);
+        let (header, csr) = cache::read(&path).map_err(|err| cache::read_error(&path, err))?;
+
+        let ids = NodeIds::new(csr.node_ids.iter().map(|&id| NodeId(id)).collect());
+        let positions = csr
+            .coords
+            .iter()
+            .map(|&(decimicro_lat, decimicro_lon)| Position {
+                decimicro_lat,
+                decimicro_lon,
+            })
+            .collect();
+        let adj_list = csr
+            .offsets
+            .iter()
+            .tuple_windows()
+            .map(|(&begin, &end)| {
+                (begin as usize..end as usize)
+                    .map(|e| Edge {
+                        target: csr.targets[e],
+                        length: csr.lengths[e] as f64,
+                        cost: csr.costs[e] as f64,
+                        major: csr.edge_flags[e] & cache::MAJOR_EDGE != 0,
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut geometries = HashMap::new();
+        for (i, (&begin, &end)) in csr.offsets.iter().tuple_windows().enumerate() {
+            for e in begin as usize..end as usize {
+                let vias = (csr.via_offsets[e]..csr.via_offsets[e + 1])
+                    .map(|v| Via {
+                        node: csr.via_nodes[v as usize],
+                        length: csr.via_lengths[v as usize] as f64,
+                        cost: csr.via_costs[v as usize] as f64,
+                    })
+                    .collect_vec();
+                if !vias.is_empty() {
+                    geometries.insert((i as NodeIdx, csr.targets[e]), vias);
+                }
+            }
+        }
+        let elevations = if csr.elevations.iter().all(|e| e.is_nan()) {
+            Vec::new()
+        } else {
+            csr.elevations.iter().map(|&e| e as f64).collect()
+        };
+
+        let restrictions = csr
+            .restriction_offsets
+            .iter()
+            .tuple_windows()
+            .zip(&csr.restriction_kinds)
+            .map(|((&begin, &end), &kind)| TurnRestriction {
+                kind,
+                nodes: csr.restriction_nodes[begin as usize..end as usize]
+                    .iter()
+                    .map(|&idx| ids.id(idx))
+                    .collect(),
+            })
+            .collect();
+
+        Ok(
+            Self::from_parts(ids, positions, adj_list, elevations, geometries, header)
+                .with_restrictions(restrictions)
+                .with_turn_costs(profile.turn_costs.clone())
+                .with_min_cost_per_meter(profile.min_cost_per_meter()),
+        )
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> Result<(), Error> {
+        let mut csr = CsrGraph {
+            node_ids: self.ids.ids.iter().map(|id| id.0).collect(),
+            coords: self
+                .positions
+                .iter()
+                .map(|p| (p.decimicro_lat, p.decimicro_lon))
+                .collect(),
+            ..Default::default()
+        };
+
+        csr.offsets.push(0);
+        csr.via_offsets.push(0);
+        for idx in 0..self.node_count() as NodeIdx {
+            csr.elevations
+                .push(self.elevation(idx).map_or(f32::NAN, |e| e as f32));
+            for edge in self.edges(idx) {
+                csr.targets.push(edge.target);
+                csr.lengths.push(edge.length as f32);
+                csr.costs.push(edge.cost as f32);
+                csr.edge_flags
+                    .push(if edge.major { cache::MAJOR_EDGE } else { 0 });
+                for via in self.geometry(idx, edge.target) {
+                    csr.via_nodes.push(via.node);
+                    csr.via_lengths.push(via.length as f32);
+                    csr.via_costs.push(via.cost as f32);
+                }
+                csr.via_offsets.push(csr.via_nodes.len() as u32);
+            }
+            csr.offsets.push(csr.targets.len() as u32);
+        }
+        csr.restriction_offsets.push(0);
+        for restriction in &self.restrictions {
+            csr.restriction_nodes
+                .extend(restriction.nodes.iter().filter_map(|&id| self.index_of(id)));
+            csr.restriction_offsets
+                .push(csr.restriction_nodes.len() as u32);
+            csr.restriction_kinds.push(restriction.kind);
+        }
+
+        fs::create_dir_all(cache_dir)?;
+        Ok(cache::write(
+            &Self::cache_path(cache_dir, &self.header.profile),
+            &self.header,
+            &csr,
+        )?)
+    }
+
+    /// Index of the node with OSM ID `id`, if the graph has it.
+    pub fn index_of(&self, id: NodeId) -> Option<NodeIdx> {
+        self.ids.index(id)
+    }
+
+    /// OSM ID of the node at `idx`.
+    pub fn id_of(&self, idx: NodeIdx) -> NodeId {
+        self.ids.id(idx)
+    }
+
+    /// Where the node at `idx` is, unless it was only given as an end of edges.
+    pub fn position(&self, idx: NodeIdx) -> Option<Position> {
+        Some(self.positions[idx as usize]).filter(|&p| p != Position::UNKNOWN)
+    }
+
+    /// Nodes with a known position.
+    pub fn positions(&self) -> impl Iterator<Item = (NodeIdx, Position)> + '_ {
+        (0..self.node_count() as NodeIdx).filter_map(|idx| Some((idx, self.position(idx)?)))
+    }
+
+    /// Nodes with outgoing edges, i.e. those a route can start from.
+    pub fn sources(&self) -> impl Iterator<Item = NodeIdx> + '_ {
+        (0..self.node_count() as NodeIdx).filter(|&idx| !self.edges(idx).is_empty())
+    }
+
+    /// Nodes passed between `source` and `target` along the edge joining them,
+    /// empty unless it collapses a chain.
+    pub fn geometry(&self, source: NodeIdx, target: NodeIdx) -> &[Via] {
+        self.geometries
+            .get(&(source, target))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether the graph has a node with OSM ID `id`.
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.index_of(id).is_some()
+    }
+
+    /// Whether `idx` has any edges, i.e. wasn't collapsed into one.
+    pub fn is_junction(&self, idx: NodeIdx) -> bool {
+        !self.edges(idx).is_empty() || !self.incoming_edges(idx).is_empty()
+    }
+
+    /// Where the graph was built from, as recorded in its cache.
+    pub fn header(&self) -> &CacheHeader {
+        &self.header
+    }
+
+    /// Elevation of `idx` in meters, if the graph was built with a DEM covering it.
+    pub fn elevation(&self, idx: NodeIdx) -> Option<f64> {
+        known_elevation(&self.elevations, idx)
+    }
+
+    /// Turn restrictions to check while stepping along edges.
+    pub fn turns(&self) -> &TurnRestrictions {
+        &self.turns
+    }
+
+    pub fn restriction_count(&self) -> usize {
+        self.restrictions.len()
+    }
+
+    /// Penalty of passing `via` coming from `from` and leaving along `edge`,
+    /// zero unless the graph has turn costs.
+    pub fn turn_cost(&self, from: NodeIdx, via: NodeIdx, edge: &Edge) -> f64 {
+        let Some(turn_costs) = &self.turn_costs else {
+            return 0.0;
+        };
+        // Angles are taken at the nodes next to `via`, which may be inside
+        // the edges.
+        let before = self.geometry(from, via).last().map_or(from, |v| v.node);
+        let after = self
+            .geometry(via, edge.target)
+            .first()
+            .map_or(edge.target, |v| v.node);
+        let (Some(a), Some(b), Some(c)) = (
+            self.position(before),
+            self.position(via),
+            self.position(after),
+        ) else {
+            return 0.0;
+        };
+        let arrives_on_major = self.edges(from).iter().any(|e| e.target == via && e.major);
+        turn_costs.cost(
+            turn_angle(a, b, c),
+            edge.target == from,
+            !arrives_on_major && !edge.major && self.major_nodes[via as usize],
+        )
+    }
+
+    /// No edge costs less than this per meter of its length.
+    pub fn min_cost_per_meter(&self) -> f64 {
+        self.min_cost_per_meter
+    }
+
+    /// Whether routers have to track the edge a node is reached by.
+    pub fn is_edge_based(&self) -> bool {
+        self.turn_costs.is_some()
+    }
+
+    /// Outgoing edges of `idx`.
+    pub fn edges(&self, idx: NodeIdx) -> &[Edge] {
+        &self.adj_list[idx as usize]
+    }
+
+    /// Incoming edges of `idx`, with `target` being the node they come from.
+    pub fn incoming_edges(&self, idx: NodeIdx) -> &[Edge] {
+        &self.reverse_adj_list[idx as usize]
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.adj_list.iter().map(Vec::len).sum()
+    }
+}
+
+/// Peak resident memory of the process in bytes, where the OS tells it.
+fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// The elevation at `idx` in `elevations`, unless unknown.
+fn known_elevation(elevations: &[f64], idx: NodeIdx) -> Option<f64> {
+    elevations
+        .get(idx as usize)
+        .copied()
+        .filter(|elevation| !elevation.is_nan())
+}
+
+/// The values of nodes that are `kept`.
+fn retain_kept<T>(values: Vec<T>, kept: &[bool]) -> Vec<T> {
+    values
+        .into_iter()
+        .zip(kept)
+        .filter_map(|(value, &kept)| kept.then_some(value))
+        .collect()
+}
+
+/// Pieces of `way` between the nodes that aren't `present`, leaving out the
+/// ones too short to hold an edge.
+fn split_at_missing_nodes(way: &Way, present: impl Fn(&NodeId) -> bool) -> Vec<Way> {
+    way.nodes
+        .split(|id| !present(id))
+        .filter(|run| run.len() > 1)
+        .map(|run| Way {
+            id: way.id,
+            tags: way.tags.clone(),
+            nodes: run.to_vec(),
+        })
+        .collect()
+}
+
+/// Adds edges between the consecutive nodes of `way` in the directions
+/// `profile` allows it to be travelled.
+fn add_way(
+    adj_list: &mut [Vec<Edge>],
+    way: &Way,
+    ids: &NodeIds,
+    positions: &[Position],
+    elevations: &[f64],
+    profile: &Profile,
+) {
+    let major = profile.is_major(&way.tags);
+    let (forward, backward) = match profile.direction(&way.tags) {
+        Direction::Both => (true, true),
+        Direction::Forward => (true, false),
+        Direction::Backward => (false, true),
+        Direction::Closed => return,
+    };
+
+    let indices = way.nodes.iter().map(|&id| ids.index(id).unwrap());
+    for (u, v) in indices.tuple_windows() {
+        let (a, b) = (positions[u as usize], positions[v as usize]);
+        let length =
+            point!(x: a.lon(), y: a.lat()).haversine_distance(&point!(x: b.lon(), y: b.lat()));
+        let cost = profile.edge_cost(&way.tags, length);
+        let climb = match (
+            known_elevation(elevations, u),
+            known_elevation(elevations, v),
+        ) {
+            (Some(from), Some(to)) => to - from,
+            _ => 0.0,
+        };
+
+        if forward {
+            adj_list[u as usize].push(Edge {
+                target: v,
+                length,
+                cost: cost + profile.climb_penalty * climb.max(0.0),
+                major,
+            });
+        }
+        if backward {
+            adj_list[v as usize].push(Edge {
+                target: u,
+                length,
+                cost: cost + profile.climb_penalty * (-climb).max(0.0),
+                major,
+            });
+        }
+    }
+}
+
+/// The edges of `adj_list` turned around.
+fn reverse(adj_list: &[Vec<Edge>]) -> Vec<Vec<Edge>> {
+    let mut reverse_adj_list = vec![Vec::new(); adj_list.len()];
+    for (source, edges) in adj_list.iter().enumerate() {
+        for edge in edges {
+            reverse_adj_list[edge.target as usize].push(Edge {
+                target: source as NodeIdx,
+                ..*edge
+            });
+        }
+    }
+    reverse_adj_list
+}
+
+/// Whether each node of `adj_list` has a major edge.
+fn major_nodes(adj_list: &[Vec<Edge>]) -> Vec<bool> {
+    let mut major = vec![false; adj_list.len()];
+    for (source, edges) in adj_list.iter().enumerate() {
+        for edge in edges.iter().filter(|edge| edge.major) {
+            major[source] = true;
+            major[edge.target as usize] = true;
+        }
+    }
+    major
+}
+
+/// Degrees between the directions of `a -> b` and `b -> c`, 0 for straight on.
+fn turn_angle(a: Position, b: Position, c: Position) -> f64 {
+    let bearing = |from: Position, to: Position| {
+        let dx = (to.lon() - from.lon()) * from.lat().to_radians().cos();
+        (to.lat() - from.lat()).atan2(dx)
+    };
+    let diff = (bearing(b, c) - bearing(a, b))
+        .to_degrees()
+        .rem_euclid(360.0);
+    diff.min(360.0 - diff)
+}
+
+/// How a DEM directory is recorded in the cache header, with a hash of its
+/// tiles so that replacing them makes the cache stale, or empty if none is
+/// used.
+fn dem_name(dem_dir: Option<&Path>) -> String {
+    dem_dir.map_or_else(String::new, |dir| match elevation::tiles_fingerprint(dir) {
+        Ok(hash) => format!("{} ({:016x})", dir.display(), hash),
+        // Building fails on the directory anyway.
+        Err(_) => dir.display().to_string(),
+    })
+}
+
+/// Header of a graph built from `pbf_path` with `profile` and `dem_dir`.
+pub(crate) fn expected_header(
+    pbf_path: &Path,
+    profile: &Profile,
+    dem_dir: Option<&Path>,
+) -> io::Result<CacheHeader> {
+    let pbf_header = fingerprint::read_header_block(&mut BufReader::new(File::open(pbf_path)?))?;
+    Ok(CacheHeader {
+        version: cache::FORMAT_VERSION,
+        source_pbf: pbf_path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        pbf: PbfFingerprint::of(pbf_path)?,
+        profile: profile.name.clone(),
+        profile_hash: profile.fingerprint(),
+        dem: dem_name(dem_dir),
+        replication_sequence: pbf_header.get_osmosis_replication_sequence_number(),
+        revision: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::WayId;
+
+    use super::*;
+    use crate::{
+        components::tests::island_graph, fingerprint::tests::write_pbf,
+        restriction::RestrictionKind,
+    };
+
+    const CAR_PROFILE: &str = include_str!("../profiles/car.toml");
+    const FOOT_PROFILE: &str = include_str!("../profiles/foot.toml");
+
+    /// Directed edges built from a way through nodes 1, 2 and 3 with `tags`.
+    fn edges_of(tags: &[(&str, &str)], profile: &Profile) -> Vec<(i64, i64)> {
+        let ids = NodeIds::new([1, 2, 3].map(NodeId).to_vec());
+        let positions = (1..=3)
+            .map(|i| Position {
+                decimicro_lat: 356_800_000 + i * 1000,
+                decimicro_lon: 1_397_600_000,
+            })
+            .collect_vec();
+        let way = Way {
+            id: WayId(1),
+            tags: tags.iter().map(|&(k, v)| (k.into(), v.into())).collect(),
+            nodes: [1, 2, 3].map(NodeId).to_vec(),
+        };
+
+        let mut adj_list = vec![Vec::new(); 3];
+        add_way(&mut adj_list, &way, &ids, &positions, &[], profile);
+        adj_list
+            .iter()
+            .enumerate()
+            .flat_map(|(u, edges)| {
+                let ids = &ids;
+                edges
+                    .iter()
+                    .map(move |edge| (ids.id(u as NodeIdx).0, ids.id(edge.target).0))
+            })
+            .sorted()
+            .collect()
+    }
+
+    const BOTH: [(i64, i64); 4] = [(1, 2), (2, 1), (2, 3), (3, 2)];
+    const FORWARD: [(i64, i64); 2] = [(1, 2), (2, 3)];
+    const BACKWARD: [(i64, i64); 2] = [(2, 1), (3, 2)];
+
+    fn bicycle() -> Profile {
+        Profile::bicycle()
+    }
+
+    fn car() -> Profile {
+        Profile::parse(CAR_PROFILE, "car.toml").unwrap()
+    }
+
+    #[test]
+    fn untagged_way_is_bidirectional() {
+        assert_eq!(edges_of(&[("highway", "residential")], &bicycle()), BOTH);
+    }
+
+    #[test]
+    fn oneway_yes_true_and_1_follow_the_way() {
+        for value in ["yes", "true", "1"] {
+            let tags = [("highway", "residential"), ("oneway", value)];
+            assert_eq!(edges_of(&tags, &bicycle()), FORWARD, "oneway={}", value);
+        }
+    }
+
+    #[test]
+    fn oneway_minus_1_goes_against_the_way() {
+        let tags = [("highway", "residential"), ("oneway", "-1")];
+        assert_eq!(edges_of(&tags, &bicycle()), BACKWARD);
+    }
+
+    #[test]
+    fn oneway_no_is_bidirectional() {
+        let tags = [("highway", "residential"), ("oneway", "no")];
+        assert_eq!(edges_of(&tags, &bicycle()), BOTH);
+    }
+
+    #[test]
+    fn reversible_ways_have_no_edges() {
+        for value in ["reversible", "alternating"] {
+            let tags = [("highway", "primary"), ("oneway", value)];
+            assert_eq!(edges_of(&tags, &car()), [], "oneway={}", value);
+        }
+    }
+
+    #[test]
+    fn roundabouts_are_implied_oneway() {
+        let tags = [("highway", "primary"), ("junction", "roundabout")];
+        assert_eq!(edges_of(&tags, &car()), FORWARD);
+
+        let tags = [
+            ("highway", "primary"),
+            ("junction", "roundabout"),
+            ("oneway", "no"),
+        ];
+        assert_eq!(edges_of(&tags, &car()), BOTH);
+    }
+
+    #[test]
+    fn oneway_bicycle_no_only_exempts_bicycles() {
+        let tags = [
+            ("highway", "residential"),
+            ("oneway", "yes"),
+            ("oneway:bicycle", "no"),
+        ];
+        assert_eq!(edges_of(&tags, &bicycle()), BOTH);
+        assert_eq!(edges_of(&tags, &car()), FORWARD);
+
+        let tags = [
+            ("highway", "residential"),
+            ("junction", "roundabout"),
+            ("oneway:bicycle", "no"),
+        ];
+        assert_eq!(edges_of(&tags, &bicycle()), BOTH);
+        assert_eq!(edges_of(&tags, &car()), FORWARD);
+    }
+
+    #[test]
+    fn oneway_bicycle_yes_only_restricts_bicycles() {
+        let tags = [("highway", "residential"), ("oneway:bicycle", "yes")];
+        assert_eq!(edges_of(&tags, &bicycle()), FORWARD);
+        assert_eq!(edges_of(&tags, &car()), BOTH);
+    }
+
+    #[test]
+    fn opposite_cycleways_allow_contraflow_for_bicycles() {
+        for (key, value) in [
+            ("cycleway", "opposite"),
+            ("cycleway", "opposite_lane"),
+            ("cycleway:left", "opposite_track"),
+        ] {
+            let tags = [("highway", "residential"), ("oneway", "yes"), (key, value)];
+            assert_eq!(edges_of(&tags, &bicycle()), BOTH, "{}={}", key, value);
+            assert_eq!(edges_of(&tags, &car()), FORWARD, "{}={}", key, value);
+        }
+
+        let tags = [
+            ("highway", "residential"),
+            ("oneway", "-1"),
+            ("cycleway", "opposite_lane"),
+        ];
+        assert_eq!(edges_of(&tags, &bicycle()), BOTH);
+    }
+
+    #[test]
+    fn contraflow_does_not_open_reversible_ways() {
+        let tags = [
+            ("highway", "residential"),
+            ("oneway", "reversible"),
+            ("cycleway", "opposite"),
+        ];
+        assert_eq!(edges_of(&tags, &bicycle()), []);
+    }
+
+    #[test]
+    fn ignoring_oneways_is_always_bidirectional() {
+        let foot = Profile::parse(FOOT_PROFILE, "foot.toml").unwrap();
+        for value in ["yes", "-1", "reversible"] {
+            let tags = [("highway", "residential"), ("oneway", value)];
+            assert_eq!(edges_of(&tags, &foot), BOTH, "oneway={}", value);
+        }
+    }
+
+    #[test]
+    fn ways_are_split_at_missing_nodes() {
+        let way = Way {
+            id: WayId(1),
+            tags: [("highway".into(), "residential".into())]
+                .into_iter()
+                .collect(),
+            nodes: (1..=9).map(NodeId).collect(),
+        };
+
+        let present = |id: &NodeId| [1, 2, 4, 5, 6, 8].contains(&id.0);
+        let pieces = split_at_missing_nodes(&way, present)
+            .into_iter()
+            .map(|piece| piece.nodes.iter().map(|id| id.0).collect_vec())
+            .collect_vec();
+        assert_eq!(pieces, [vec![1, 2], vec![4, 5, 6]]);
+    }
+
+    #[test]
+    fn corrupt_caches_are_reported() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("read-osm-corrupt-{}", std::process::id()));
+        fs::create_dir_all(&cache_dir).unwrap();
+        let profile = bicycle();
+        let path = RoadGraph::cache_path(&cache_dir, &profile.name);
+        // A header cut short after the format version.
+        let mut bytes = b"ROSMGRPH".to_vec();
+        bytes.extend(cache::FORMAT_VERSION.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let result = RoadGraph::load(&cache_dir, &profile);
+        fs::remove_dir_all(&cache_dir).unwrap();
+        assert!(matches!(result, Err(Error::CacheCorrupt { path: p, .. }) if p == path));
+    }
+
+    #[test]
+    fn caches_are_checked_against_their_pbf_and_profile() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("read-osm-staleness-{}", std::process::id()));
+        fs::create_dir_all(&cache_dir).unwrap();
+        let pbf = cache_dir.join("test.osm.pbf");
+        write_pbf(&pbf, 1_700_000_000, &[]);
+        let profile = bicycle();
+        let check = |profile: &Profile, dem_dir: Option<&Path>| {
+            RoadGraph::check_cache(&cache_dir, &pbf, profile, dem_dir)
+        };
+
+        let missing = check(&profile, None);
+        let mut graph = island_graph();
+        graph.header = expected_header(&pbf, &profile, None).unwrap();
+        graph.save(&cache_dir).unwrap();
+        let fresh = check(&profile, None);
+        let other_dem = check(&profile, Some(Path::new("dem")));
+        let mut other_profile = profile.clone();
+        other_profile.default_speed += 1.0;
+        let changed_profile = check(&other_profile, None);
+        write_pbf(&pbf, 1_700_086_400, &[]);
+        let replaced_pbf = check(&profile, None);
+        fs::remove_file(&pbf).unwrap();
+        let missing_pbf = check(&profile, None);
+        fs::remove_dir_all(&cache_dir).unwrap();
+
+        assert_eq!(missing, CacheStatus::Missing);
+        assert_eq!(fresh, CacheStatus::Fresh);
+        for status in [other_dem, changed_profile, replaced_pbf] {
+            assert!(matches!(status, CacheStatus::Stale(_)), "{:?}", status);
+        }
+        assert_eq!(missing_pbf, CacheStatus::PbfMissing);
+    }
+
+    #[test]
+    fn graphs_are_loaded_as_saved() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("read-osm-round-trip-{}", std::process::id()));
+        let profile = bicycle();
+        let mut graph = island_graph().with_restrictions(vec![TurnRestriction {
+            kind: RestrictionKind::No,
+            nodes: [1, 2, 3].map(NodeId).to_vec(),
+        }]);
+        graph.header.profile = profile.name.clone();
+        graph.header.replication_sequence = 4321;
+        graph.header.revision = 3;
+        graph.save(&cache_dir).unwrap();
+        let loaded = RoadGraph::load(&cache_dir, &profile);
+        fs::remove_dir_all(&cache_dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.header(), graph.header());
+        assert_eq!(loaded.ids.ids, graph.ids.ids);
+        assert_eq!(loaded.positions, graph.positions);
+        assert_eq!(loaded.adj_list, graph.adj_list);
+        assert_eq!(loaded.geometries, graph.geometries);
+        assert_eq!(loaded.restrictions, graph.restrictions);
+    }
+}
@@ -0,0 +1,30 @@
+//! Build a cyclable road graph from OpenStreetMap PBF extracts and find routes on it.
+
+pub mod cache;
+pub mod cch;
+pub mod ch;
+pub mod components;
+pub mod download;
+pub mod elevation;
+pub mod error;
+pub mod extract;
+pub mod fingerprint;
+pub mod graph;
+pub mod osc;
+pub mod profile;
+pub mod progress;
+pub mod restriction;
+pub mod route;
+pub mod router;
+pub mod snap;
+
+pub use cch::CustomizableContractionHierarchy;
+pub use ch::ContractionHierarchy;
+pub use components::Components;
+pub use error::Error;
+pub use extract::Extract;
+pub use graph::{CacheStatus, NodeIdx, RoadGraph};
+pub use profile::Profile;
+pub use route::Route;
+pub use router::{AStar, BidirectionalDijkstra, Dijkstra, Router};
+pub use snap::NodeIndex;
@@ -1,191 +1,483 @@
 use std::{
-    cmp::Reverse,
-    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
-    fs::{self, File},
-    io::{BufReader, BufWriter},
-    path::Path,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
     time::Instant,
 };
 
-use geo::{coord, point, HaversineDistance};
 use itertools::Itertools;
-use osmpbfreader::{Node, NodeId, OsmObj, OsmPbfReader, Way};
-
-const ADJ_LIST_JSON_PATH: &str = "data/adj-list.json";
-const NODES_JSON_PATH: &str = "data/nodes.json";
-const PBF_PATH: &str = "data/japan-latest.osrm.pbf";
-const RESULT_PATH: &str = "data/result-polyline.txt";
-
-const INACCESSIBLE_TAGS: [(&str, &str); 7] = [
-    ("highway", "motorway"),
-    ("highway", "motorway_link"),
-    // ref: https://github.com/team-azb/route-bucket-backend/blob/master/osrm/customized.lua#L54
-    ("access", "agricultural"),
-    ("access", "delivery"),
-    ("access", "forestry"),
-    ("access", "delivery"),
-    ("access", "use_sidepath"),
-];
-
-const START_NODE_ID: NodeId = NodeId(5798366045); // https://www.openstreetmap.org/node/5798366045
-const GOAL_NODE_ID: NodeId = NodeId(1254449298); // https://www.openstreetmap.org/node/1254449298
-
-fn download_pbf(pbf_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let mut resp =
-        reqwest::blocking::get("https://download.geofabrik.de/asia/japan-latest.osm.pbf")?;
-    let mut pbf_file = BufWriter::new(File::create(pbf_path)?);
-    resp.copy_to(&mut pbf_file)?;
-    Ok(())
-}
-
-fn is_cyclable_way(way: &Way) -> bool {
-    let is_road = way.tags.contains_key("highway");
-    let is_paved = !way.tags.contains_key("surface")
-        || way.tags.contains("surface", "paved")
-        || way.tags.contains("surface", "asphalt")
-        || way.tags.contains("surface", "concrete")
-        || way.tags.contains("surface", "paving_stones");
-    let is_accessible = INACCESSIBLE_TAGS
-        .iter()
-        .all(|(key, value)| !way.tags.contains(key, value));
-
-    is_road && is_paved && is_accessible
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let timer = Instant::now();
 
-    let mut nodes = HashMap::<NodeId, Node>::new();
-    let mut adj_list = HashMap::<NodeId, Vec<(NodeId, f64)>>::new();
-    let nodes_json_path = Path::new(NODES_JSON_PATH);
-    let adj_list_json_path = Path::new(ADJ_LIST_JSON_PATH);
-
-    if nodes_json_path.exists() && adj_list_json_path.exists() {
-        nodes = serde_json::from_reader(BufReader::new(File::open(nodes_json_path)?))?;
-        adj_list = serde_json::from_reader(BufReader::new(File::open(adj_list_json_path)?))?;
-    } else {
-        let pbf_path = Path::new(PBF_PATH);
-        if !pbf_path.exists() {
-            download_pbf(pbf_path).or_else(|err| {
-                fs::remove_file(pbf_path)?;
-                Err(err)
-            })?;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use geo::{point, HaversineDistance};
+use osmpbfreader::NodeId;
+use read_osm::{
+    download::{ensure_pbf, file_name, geofabrik_updates_url, geofabrik_url, DEFAULT_REGION},
+    osc::{self, OsmChange},
+    progress, AStar, BidirectionalDijkstra, CacheStatus, Components, ContractionHierarchy,
+    CustomizableContractionHierarchy, Dijkstra, Error, Extract, NodeIndex, Profile, RoadGraph,
+    Router,
+};
+
+const DEFAULT_CACHE_DIR: &str = "data";
+const DEFAULT_PBF_DIR: &str = "data";
+const DEFAULT_RESULT_PATH: &str = "data/result-polyline.txt";
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Build a cyclable road graph from OSM data and route on it",
+    after_help = "Exits with status 2 if there is no route, 3 if a waypoint is not in the graph \
+                  and 1 on other errors."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Don't draw progress bars or print status lines, e.g. when scripting
+    #[arg(long, global = true)]
+    quiet: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Download an extract, by default of Japan from Geofabrik
+    Download(SourceArgs),
+    /// Build the road graph from a PBF and write it to the cache directory
+    BuildGraph(GraphArgs),
+    /// Build a contraction hierarchy of the cached graph for `--algorithm ch`
+    BuildCh(GraphArgs),
+    /// Order the cached graph for customizable contraction hierarchies
+    BuildCch(GraphArgs),
+    /// Apply the costs of a profile to a hierarchy from `build-cch`, for `--algorithm ch`
+    Customize {
+        #[command(flatten)]
+        graph: GraphArgs,
+        /// Hierarchy written by `build-cch`, possibly with a profile differing only in costs
+        #[arg(long)]
+        cch: PathBuf,
+    },
+    /// Apply OsmChange diffs to the cached graph, by default all diffs of the
+    /// region published since it was built or last updated
+    Update {
+        #[command(flatten)]
+        graph: GraphArgs,
+        /// OsmChange files or URLs to apply in order instead, `.osc` or `.osc.gz`
+        #[arg(long, num_args = 1..)]
+        osc: Vec<String>,
+        /// Replication sequence number of the last of the `--osc` diffs
+        #[arg(long, requires = "osc")]
+        sequence: Option<i64>,
+        /// Replication server to fetch the diffs from [default: that of the
+        /// Geofabrik region]
+        #[arg(long, conflicts_with = "osc")]
+        replication_url: Option<String>,
+    },
+    /// Find the shortest route between two points
+    Route {
+        #[command(flatten)]
+        graph: GraphArgs,
+        /// Where to start from, as `LAT,LON` or an OSM node ID
+        #[arg(long, allow_hyphen_values = true)]
+        start: Waypoint,
+        /// Where to finish at, as `LAT,LON` or an OSM node ID
+        #[arg(long, allow_hyphen_values = true)]
+        goal: Waypoint,
+        /// Where to write the encoded polyline of the route
+        #[arg(long, default_value = DEFAULT_RESULT_PATH)]
+        output: PathBuf,
+        /// Search algorithm
+        #[arg(long, value_enum, default_value_t = Algorithm::Astar)]
+        algorithm: Algorithm,
+        /// Where to write the elevation profile of the route as CSV
+        #[arg(long)]
+        elevation_profile: Option<PathBuf>,
+        /// Fail instead of rebuilding the graph when the cache is stale
+        #[arg(long)]
+        no_rebuild: bool,
+        /// Snap coordinates only to the largest strongly connected component
+        #[arg(long)]
+        main_component: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Algorithm {
+    Dijkstra,
+    Astar,
+    Bidirectional,
+    /// Contraction hierarchy saved by `build-ch`
+    Ch,
+}
+
+impl Algorithm {
+    /// The router, unless the algorithm needs preprocessed data.
+    fn router(self) -> Option<&'static dyn Router> {
+        match self {
+            Algorithm::Dijkstra => Some(&Dijkstra),
+            Algorithm::Astar => Some(&AStar),
+            Algorithm::Bidirectional => Some(&BidirectionalDijkstra),
+            Algorithm::Ch => None,
         }
+    }
+}
 
-        let mut pbf_reader = OsmPbfReader::new(BufReader::new(File::open(PBF_PATH)?));
-        let mut ways = Vec::<Way>::new();
-        for osm_obj in pbf_reader.par_iter().map(Result::unwrap) {
-            match osm_obj {
-                OsmObj::Node(node) => {
-                    if nodes.len() == 0 {
-                        println!(
-                            "First node: ({}, {}), {:?}",
-                            node.lat(),
-                            node.lon(),
-                            node.tags
-                        );
-                    }
-                    nodes.insert(node.id, node);
-                }
-                OsmObj::Way(way) => {
-                    if !is_cyclable_way(&way) {
-                        continue;
-                    }
-                    if ways.len() == 0 {
-                        println!("First way: ({:?})", way);
-                    }
-                    ways.push(way);
-                }
-                _ => {}
-            }
+/// Where an extract is read from and downloaded from.
+#[derive(Args)]
+struct SourceArgs {
+    /// PBF file, downloaded first if it doesn't exist [default: data/ and the
+    /// name of the downloaded file]
+    #[arg(long)]
+    pbf: Option<PathBuf>,
+    /// Geofabrik region to download, e.g. `europe/germany`
+    #[arg(long, default_value = DEFAULT_REGION)]
+    region: String,
+    /// URL to download instead of a Geofabrik region
+    #[arg(long, conflicts_with = "region")]
+    url: Option<String>,
+}
+
+impl SourceArgs {
+    fn url(&self) -> String {
+        self.url
+            .clone()
+            .unwrap_or_else(|| geofabrik_url(&self.region))
+    }
+
+    /// Where the diffs of the extract are published, if known.
+    fn replication_url(&self) -> Option<String> {
+        self.url
+            .is_none()
+            .then(|| geofabrik_updates_url(&self.region))
+    }
+
+    fn pbf(&self) -> PathBuf {
+        self.pbf
+            .clone()
+            .unwrap_or_else(|| Path::new(DEFAULT_PBF_DIR).join(file_name(&self.url())))
+    }
+
+    /// The PBF file, downloaded first if it doesn't exist.
+    fn ensure_pbf(&self) -> Result<PathBuf, Error> {
+        let pbf = self.pbf();
+        ensure_pbf(&self.url(), &pbf)?;
+        Ok(pbf)
+    }
+}
+
+#[derive(Args)]
+struct GraphArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+    /// Directory holding the cached graph
+    #[arg(long, default_value = DEFAULT_CACHE_DIR)]
+    cache_dir: PathBuf,
+    /// Routing profile file, e.g. `profiles/foot.toml` [default: built-in bicycle]
+    #[arg(long)]
+    profile: Option<PathBuf>,
+    /// Directory of SRTM `.hgt` tiles to take node elevations from
+    #[arg(long)]
+    dem: Option<PathBuf>,
+}
+
+impl GraphArgs {
+    fn profile(&self) -> Result<Profile, Error> {
+        match &self.profile {
+            Some(path) => Profile::from_file(path),
+            None => Ok(Profile::bicycle()),
         }
+    }
+}
+
+/// A route endpoint given on the command line.
+#[derive(Clone, Copy)]
+enum Waypoint {
+    Node(NodeId),
+    Coord { lat: f64, lon: f64 },
+}
 
-        println!(
-            "Pre computation done: {} nodes, {} ways ({}s)",
-            nodes.len(),
-            ways.len(),
-            timer.elapsed().as_secs_f64()
-        );
-
-        let mut node_ids = HashSet::<NodeId>::new();
-        for way in ways {
-            let is_bidirectional = !way.tags.contains("oneway", "yes");
-            way.nodes.iter().tuple_windows().for_each(|(&u, &v)| {
-                node_ids.insert(u);
-                node_ids.insert(v);
-
-                let edge_len = point!(x: nodes[&u].lon(), y: nodes[&u].lat())
-                    .haversine_distance(&point!( x: nodes[&v].lon(), y: nodes[&v].lat()));
-
-                adj_list.entry(u).or_insert(Vec::new()).push((v, edge_len));
-                if is_bidirectional {
-                    adj_list.entry(v).or_insert(Vec::new()).push((u, edge_len));
+impl FromStr for Waypoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(',') {
+            Some((lat, lon)) => {
+                let lat = lat.trim().parse::<f64>().map_err(|e| e.to_string())?;
+                let lon = lon.trim().parse::<f64>().map_err(|e| e.to_string())?;
+                if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                    return Err(format!("({}, {}) is not a valid coordinate", lat, lon));
                 }
-            });
+                Ok(Waypoint::Coord { lat, lon })
+            }
+            None => Ok(Waypoint::Node(NodeId(
+                s.trim()
+                    .parse()
+                    .map_err(|e: std::num::ParseIntError| e.to_string())?,
+            ))),
         }
+    }
+}
 
-        nodes = nodes
-            .into_iter()
-            .filter(|(id, _)| node_ids.contains(id))
-            .collect();
+fn resolve_waypoint(
+    waypoint: Waypoint,
+    graph: &RoadGraph,
+    index: &NodeIndex,
+) -> Result<NodeId, Box<dyn std::error::Error>> {
+    match waypoint {
+        Waypoint::Node(node_id) => Ok(node_id),
+        Waypoint::Coord { lat, lon } => {
+            let idx = index.nearest(lat, lon).ok_or("the graph has no nodes")?;
+            let pos = graph.position(idx).ok_or("snapped to an unknown node")?;
+            let node_id = graph.id_of(idx);
+            progress::status(format_args!(
+                "Snapped ({}, {}) to node {} at ({}, {}), {:.1} m away",
+                lat,
+                lon,
+                node_id.0,
+                pos.lat(),
+                pos.lon(),
+                point!(x: lon, y: lat).haversine_distance(&point!(x: pos.lon(), y: pos.lat()))
+            ));
+            Ok(node_id)
+        }
+    }
+}
 
-        serde_json::to_writer(BufWriter::new(File::create(nodes_json_path)?), &nodes)?;
-        serde_json::to_writer(BufWriter::new(File::create(adj_list_json_path)?), &adj_list)?;
+fn load_or_build_graph(
+    args: &GraphArgs,
+    no_rebuild: bool,
+) -> Result<RoadGraph, Box<dyn std::error::Error>> {
+    let profile = args.profile()?;
+    let pbf = args.source.pbf();
+    match RoadGraph::check_cache(&args.cache_dir, &pbf, &profile, args.dem.as_deref()) {
+        CacheStatus::Fresh => return Ok(RoadGraph::load(&args.cache_dir, &profile)?),
+        CacheStatus::PbfMissing => {
+            progress::status(format_args!(
+                "{} is missing, using the cached graph without checking it",
+                pbf.display()
+            ));
+            return Ok(RoadGraph::load(&args.cache_dir, &profile)?);
+        }
+        CacheStatus::Stale(reason) if no_rebuild => {
+            return Err(format!(
+                "cached graph in {} is stale: {}",
+                args.cache_dir.display(),
+                reason
+            )
+            .into())
+        }
+        CacheStatus::Stale(reason) => progress::status(format_args!(
+            "Cached graph is stale ({}), rebuilding it",
+            reason
+        )),
+        CacheStatus::Missing if no_rebuild => {
+            return Err(format!("no cached graph in {}", args.cache_dir.display()).into())
+        }
+        CacheStatus::Missing => {}
     }
 
-    println!(
+    Ok(build_graph(args, &profile)?)
+}
+
+/// Builds the graph from the PBF and saves it with its extract.
+fn build_graph(args: &GraphArgs, profile: &Profile) -> Result<RoadGraph, Error> {
+    let pbf = args.source.ensure_pbf()?;
+    let extract = Extract::from_pbf(&pbf, profile, args.dem.as_deref())?;
+    let graph = RoadGraph::from_extract(&extract, profile, args.dem.as_deref())?;
+    graph.save(&args.cache_dir)?;
+    extract.save(&args.cache_dir)?;
+    Ok(graph)
+}
+
+fn print_graph_summary(graph: &RoadGraph, timer: &Instant) {
+    progress::status(format_args!(
         "Graph loaded: {} nodes, {} edges ({} s)",
-        nodes.len(),
-        adj_list.values().map(|e| e.len()).sum::<usize>(),
+        graph.node_count(),
+        graph.edge_count(),
         timer.elapsed().as_secs_f64()
-    );
-
-    let mut queue = BinaryHeap::new();
-    let mut parent = HashMap::<NodeId, NodeId>::new();
-    queue.push(Reverse((0u64, START_NODE_ID)));
-    parent.insert(START_NODE_ID, NodeId(-1));
-    while let Some(Reverse((dist, current))) = queue.pop() {
-        if current == GOAL_NODE_ID {
+    ));
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    progress::set_quiet(cli.quiet);
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(match err.downcast_ref::<Error>() {
+                Some(Error::Unreachable { .. }) => 2,
+                Some(Error::NodeNotFound(_)) => 3,
+                _ => 1,
+            })
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let timer = Instant::now();
+
+    match cli.command {
+        Command::Download(source) => {
+            source.ensure_pbf()?;
+            Ok(())
+        }
+        Command::BuildGraph(args) => {
+            let graph = build_graph(&args, &args.profile()?)?;
+            print_graph_summary(&graph, &timer);
+            Ok(())
+        }
+        Command::BuildCh(args) => {
+            let graph = load_or_build_graph(&args, false)?;
+            print_graph_summary(&graph, &timer);
+            Ok(ContractionHierarchy::build(&graph).save(&args.cache_dir)?)
+        }
+        Command::BuildCch(args) => {
+            let graph = load_or_build_graph(&args, false)?;
+            print_graph_summary(&graph, &timer);
+            Ok(CustomizableContractionHierarchy::build(&graph).save(&args.cache_dir)?)
+        }
+        Command::Customize { graph: args, cch } => {
+            let cch = CustomizableContractionHierarchy::load(&cch)?;
+            let graph = load_or_build_graph(&args, false)?;
+            print_graph_summary(&graph, &timer);
+            Ok(cch.customize(&graph)?.save(&args.cache_dir)?)
+        }
+        Command::Update {
+            graph: args,
+            osc,
+            sequence,
+            replication_url,
+        } => {
+            let profile = args.profile()?;
+            let graph = load_or_build_graph(&args, false)?;
+            let mut extract = Extract::load(&args.cache_dir, &graph)?;
+            drop(graph);
+            if osc.is_empty() {
+                let base_url = replication_url
+                    .or_else(|| args.source.replication_url())
+                    .ok_or("no replication server known for --url, give --replication-url")?;
+                let first = extract.header.replication_sequence + 1;
+                if first == 1 {
+                    return Err("the PBF has no replication sequence number, give --osc".into());
+                }
+                let latest = osc::latest_sequence(&base_url)?;
+                for sequence in first..=latest {
+                    let url = osc::diff_url(&base_url, sequence);
+                    progress::status(format_args!("Applying {}", url));
+                    extract.apply(&OsmChange::load(&url)?, &profile);
+                    extract.header.replication_sequence = sequence;
+                }
+            } else {
+                for location in &osc {
+                    progress::status(format_args!("Applying {}", location));
+                    extract.apply(&OsmChange::load(location)?, &profile);
+                }
+                if let Some(sequence) = sequence {
+                    extract.header.replication_sequence = sequence;
+                }
+            }
+
+            let graph = RoadGraph::from_extract(&extract, &profile, args.dem.as_deref())?;
+            graph.save(&args.cache_dir)?;
+            extract.save(&args.cache_dir)?;
+            print_graph_summary(&graph, &timer);
+            progress::status(format_args!(
+                "Replication sequence number: {}",
+                extract.header.replication_sequence
+            ));
+            Ok(())
+        }
+        Command::Route {
+            graph: args,
+            start,
+            goal,
+            output,
+            algorithm,
+            elevation_profile,
+            no_rebuild,
+            main_component,
+        } => {
+            let mut graph = load_or_build_graph(&args, no_rebuild)?;
+            print_graph_summary(&graph, &timer);
+            let ch = match algorithm {
+                Algorithm::Ch => Some(
+                    ContractionHierarchy::load(&args.cache_dir, &graph).map_err(|e| {
+                        format!(
+                            "cannot load the contraction hierarchy, run build-ch first: {}",
+                            e
+                        )
+                    })?,
+                ),
+                _ => None,
+            };
+
+            let components = main_component.then(|| Components::new(&graph));
+            // The hierarchy only knows the junctions, while the other routers
+            // can have the chains around the waypoints split.
+            let index = NodeIndex::filtered(&graph, |idx| {
+                (ch.is_none() || graph.is_junction(idx))
+                    && components.as_ref().is_none_or(|c| c.is_main(idx))
+            });
+            let start = resolve_waypoint(start, &graph, &index)?;
+            let goal = resolve_waypoint(goal, &graph, &index)?;
+            for id in [start, goal] {
+                let Some(idx) = graph.index_of(id) else {
+                    continue;
+                };
+                match &ch {
+                    Some(_) if !graph.is_junction(idx) => {
+                        return Err(format!(
+                            "node {} lies between junctions, route from a junction or with another algorithm",
+                            id.0
+                        )
+                        .into())
+                    }
+                    Some(_) => {}
+                    None => graph.split_chains_at(idx),
+                }
+            }
+            let router = match &ch {
+                Some(ch) => ch,
+                None => algorithm.router().unwrap(),
+            };
+            let route = router.route(&graph, start, goal).inspect_err(|err| {
+                if let Error::Unreachable { .. } = err {
+                    let components = Components::new(&graph);
+                    let component = |id| graph.index_of(id).and_then(|idx| components.of(idx));
+                    if let (Some(a), Some(b)) = (component(start), component(goal)) {
+                        if a != b {
+                            eprintln!(
+                                "Start and goal are in strongly connected components of {} and \
+                                 {} nodes, try --main-component",
+                                components.sizes()[a],
+                                components.sizes()[b]
+                            );
+                        }
+                    }
+                }
+            })?;
             println!(
-                "GOOOOOAL!!! ({} s) Dist: {}",
+                "GOOOOOAL!!! ({} s) Dist: {:.1} m, Cost: {:.1} s",
                 timer.elapsed().as_secs_f64(),
-                (dist as f64) / 1000.0
+                route.distance,
+                route.cost
             );
-            break;
-        }
-        if !adj_list.contains_key(&current) {
-            continue;
-        }
+            println!("Settled {} search states", route.settled);
 
-        for (neighbor, edge_len) in &adj_list[&current] {
-            if parent.contains_key(neighbor) {
-                continue;
+            let profile = route.elevation_profile(&graph);
+            if !profile.is_empty() {
+                let (ascent, descent) = route.climb(&graph);
+                println!("Ascent: {:.0} m, Descent: {:.0} m", ascent, descent);
+            }
+            if let Some(path) = elevation_profile {
+                let rows = profile
+                    .iter()
+                    .map(|(distance, elevation)| format!("{:.1},{:.1}\n", distance, elevation))
+                    .join("");
+                fs::write(path, format!("distance_m,elevation_m\n{}", rows))?;
             }
-            queue.push(Reverse((
-                dist + (edge_len * 1000.0).round() as u64,
-                *neighbor,
-            )));
-            parent.insert(*neighbor, current);
-        }
-    }
 
-    let mut cur_id = GOAL_NODE_ID;
-    let mut coords = VecDeque::new();
-    coords.push_front(coord! {
-        x: nodes[&GOAL_NODE_ID].lon(),
-        y: nodes[&GOAL_NODE_ID].lat()
-    });
-    while cur_id != START_NODE_ID {
-        cur_id = parent[&cur_id];
-        coords.push_front(coord! {
-            x: nodes[&cur_id].lon(),
-            y: nodes[&cur_id].lat()
-        });
+            Ok(fs::write(output, route.to_polyline(&graph)?)?)
+        }
     }
-    println!("Dijkstra completed ({} s)!", timer.elapsed().as_secs_f64());
-
-    Ok(fs::write(
-        RESULT_PATH,
-        polyline::encode_coordinates(coords, 5).unwrap(),
-    )?)
 }
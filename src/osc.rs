@@ -0,0 +1,279 @@
+//! OsmChange diffs, the `.osc` or `.osc.gz` files replication servers publish
+//! a minute, hour or day of OSM edits as, to bring an
+//! [`Extract`](crate::extract::Extract) up to date.
+
+use std::{
+    fs,
+    io::{BufRead, Read},
+    str::FromStr,
+};
+
+use flate2::read::GzDecoder;
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// The objects an OsmChange creates, modifies or deletes, in the order given.
+/// Created and modified objects are given whole, and deleted ones by ID.
+#[derive(Debug, Default)]
+pub struct OsmChange {
+    pub changes: Vec<(Action, OsmObj)>,
+}
+
+impl OsmChange {
+    /// Reads the change at `location`, a file or an `http(s)://` URL, which
+    /// may be gzipped.
+    pub fn load(location: &str) -> Result<Self, Error> {
+        let bytes = if location.starts_with("http://") || location.starts_with("https://") {
+            reqwest::blocking::get(location)?
+                .error_for_status()?
+                .bytes()?
+                .to_vec()
+        } else {
+            fs::read(location)?
+        };
+        let result = if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut xml = Vec::new();
+            GzDecoder::new(&bytes[..])
+                .read_to_end(&mut xml)
+                .map_err(|err| err.to_string())
+                .and_then(|_| Self::read(&xml[..]))
+        } else {
+            Self::read(&bytes[..])
+        };
+        result.map_err(|reason| Error::OsmChange {
+            location: location.to_string(),
+            reason,
+        })
+    }
+
+    /// Parses the XML of a change.
+    pub fn read(r: impl BufRead) -> Result<Self, String> {
+        let mut reader = Reader::from_reader(r);
+        let mut buf = Vec::new();
+        let mut change = OsmChange::default();
+        let mut action = None;
+        // The object whose tags, nodes or members come next.
+        let mut current = None;
+        loop {
+            let (element, empty) = match reader.read_event_into(&mut buf) {
+                Ok(Event::Start(element)) => (element, false),
+                Ok(Event::Empty(element)) => (element, true),
+                Ok(Event::End(element)) => {
+                    match element.name().as_ref() {
+                        b"create" | b"modify" | b"delete" => action = None,
+                        b"node" | b"way" | b"relation" => {
+                            change.changes.extend(action.zip(current.take()))
+                        }
+                        _ => {}
+                    }
+                    buf.clear();
+                    continue;
+                }
+                Ok(Event::Eof) => break,
+                Ok(_) => {
+                    buf.clear();
+                    continue;
+                }
+                Err(err) => return Err(format!("{} at byte {}", err, reader.buffer_position())),
+            };
+            match element.name().as_ref() {
+                b"create" => action = Some(Action::Create),
+                b"modify" => action = Some(Action::Modify),
+                b"delete" => action = Some(Action::Delete),
+                name @ (b"node" | b"way" | b"relation") => {
+                    let Some(action) = action else {
+                        return Err("object outside create, modify or delete".to_string());
+                    };
+                    let id = attribute(&element, "id")?;
+                    let obj = match name {
+                        b"node" if action == Action::Delete => OsmObj::Node(Node {
+                            id: NodeId(id),
+                            tags: Tags::new(),
+                            decimicro_lat: 0,
+                            decimicro_lon: 0,
+                        }),
+                        b"node" => OsmObj::Node(Node {
+                            id: NodeId(id),
+                            tags: Tags::new(),
+                            decimicro_lat: decimicro(attribute(&element, "lat")?),
+                            decimicro_lon: decimicro(attribute(&element, "lon")?),
+                        }),
+                        b"way" => OsmObj::Way(Way {
+                            id: WayId(id),
+                            tags: Tags::new(),
+                            nodes: Vec::new(),
+                        }),
+                        _ => OsmObj::Relation(Relation {
+                            id: RelationId(id),
+                            tags: Tags::new(),
+                            refs: Vec::new(),
+                        }),
+                    };
+                    if empty {
+                        change.changes.push((action, obj));
+                    } else {
+                        current = Some(obj);
+                    }
+                }
+                b"tag" => {
+                    let tags = match &mut current {
+                        Some(OsmObj::Node(node)) => &mut node.tags,
+                        Some(OsmObj::Way(way)) => &mut way.tags,
+                        Some(OsmObj::Relation(relation)) => &mut relation.tags,
+                        None => return Err("tag outside an object".to_string()),
+                    };
+                    let key = attribute::<String>(&element, "k")?;
+                    let value = attribute::<String>(&element, "v")?;
+                    tags.insert(key.into(), value.into());
+                }
+                b"nd" => {
+                    let Some(OsmObj::Way(way)) = &mut current else {
+                        return Err("node reference outside a way".to_string());
+                    };
+                    way.nodes.push(NodeId(attribute(&element, "ref")?));
+                }
+                b"member" => {
+                    let Some(OsmObj::Relation(relation)) = &mut current else {
+                        return Err("member outside a relation".to_string());
+                    };
+                    let id = attribute(&element, "ref")?;
+                    let member = match attribute::<String>(&element, "type")?.as_str() {
+                        "node" => OsmId::Node(NodeId(id)),
+                        "way" => OsmId::Way(WayId(id)),
+                        "relation" => OsmId::Relation(RelationId(id)),
+                        other => return Err(format!("unknown member type {:?}", other)),
+                    };
+                    relation.refs.push(Ref {
+                        member,
+                        role: attribute::<String>(&element, "role")?.into(),
+                    });
+                }
+                _ => {}
+            }
+            buf.clear();
+        }
+        Ok(change)
+    }
+}
+
+/// Parses the attribute `key` of `element`, which must be there.
+fn attribute<T: FromStr>(element: &BytesStart, key: &str) -> Result<T, String> {
+    let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+    let value = element
+        .try_get_attribute(key)
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| format!("{} without {}", name, key))?
+        .unescape_value()
+        .map_err(|err| err.to_string())?;
+    value
+        .parse()
+        .map_err(|_| format!("{} with invalid {} {:?}", name, key, value))
+}
+
+fn decimicro(degrees: f64) -> i32 {
+    (degrees * 1e7).round() as i32
+}
+
+/// Sequence number of the latest diff on the replication server at `base_url`,
+/// from its `state.txt`.
+pub fn latest_sequence(base_url: &str) -> Result<i64, Error> {
+    let url = format!("{}/state.txt", base_url.trim_end_matches('/'));
+    let state = reqwest::blocking::get(&url)?.error_for_status()?.text()?;
+    state
+        .lines()
+        .find_map(|line| line.strip_prefix("sequenceNumber="))
+        .and_then(|number| number.trim().parse().ok())
+        .ok_or_else(|| Error::OsmChange {
+            location: url,
+            reason: "no sequenceNumber".to_string(),
+        })
+}
+
+/// URL of the diff numbered `sequence` on the replication server at
+/// `base_url`, e.g. `000/004/321.osc.gz` for 4321.
+pub fn diff_url(base_url: &str, sequence: i64) -> String {
+    format!(
+        "{}/{:03}/{:03}/{:03}.osc.gz",
+        base_url.trim_end_matches('/'),
+        sequence / 1_000_000,
+        sequence / 1000 % 1000,
+        sequence % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_read_in_order() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osmChange version="0.6" generator="test">
+  <create>
+    <node id="5" version="1" lat="35.6812" lon="139.7671"/>
+  </create>
+  <modify>
+    <way id="10" version="2">
+      <nd ref="1"/>
+      <nd ref="5"/>
+      <tag k="highway" v="residential"/>
+      <tag k="name" v="A &amp; B"/>
+    </way>
+    <relation id="20" version="3">
+      <member type="way" ref="10" role="from"/>
+      <member type="node" ref="5" role="via"/>
+      <tag k="type" v="restriction"/>
+    </relation>
+  </modify>
+  <delete>
+    <node id="3" version="4"/>
+  </delete>
+</osmChange>"#;
+
+        let change = OsmChange::read(xml.as_bytes()).unwrap();
+        assert_eq!(change.changes.len(), 4);
+        let (action, OsmObj::Node(node)) = &change.changes[0] else {
+            panic!("expected a node");
+        };
+        assert_eq!(*action, Action::Create);
+        assert_eq!(
+            (node.id, node.decimicro_lat, node.decimicro_lon),
+            (NodeId(5), 356_812_000, 1_397_671_000)
+        );
+        let (action, OsmObj::Way(way)) = &change.changes[1] else {
+            panic!("expected a way");
+        };
+        assert_eq!(*action, Action::Modify);
+        assert_eq!(way.nodes, [NodeId(1), NodeId(5)]);
+        assert!(way.tags.contains("name", "A & B"));
+        let (_, OsmObj::Relation(relation)) = &change.changes[2] else {
+            panic!("expected a relation");
+        };
+        assert_eq!(relation.refs[0].member, OsmId::Way(WayId(10)));
+        assert_eq!(relation.refs[1].role, "via");
+        assert!(matches!(
+            &change.changes[3],
+            (Action::Delete, OsmObj::Node(node)) if node.id == NodeId(3)
+        ));
+    }
+
+    #[test]
+    fn diff_urls_split_the_sequence_number() {
+        assert_eq!(
+            diff_url("https://example.com/japan-updates/", 4_321),
+            "https://example.com/japan-updates/000/004/321.osc.gz"
+        );
+    }
+}
@@ -0,0 +1,838 @@
+//! Routing profiles deciding which ways end up in the graph.
+//!
+//! A profile is a TOML file like `profiles/bicycle.toml`:
+//!
+//! ```toml
+//! name = "bicycle"
+//! oneway = "respect"
+//! oneway_keys = ["oneway", "oneway:bicycle"]
+//! cycleway_contraflow = true  # `cycleway=opposite*` allows riding against the flow
+//! min_component_size = 50  # drop strongly connected islands of fewer nodes
+//!
+//! [highway]
+//! allowed = []  # any `highway` value
+//! forbidden = ["motorway", "motorway_link"]
+//!
+//! [access]
+//! keys = ["access"]
+//! forbidden = ["agricultural", "delivery", "forestry", "use_sidepath"]
+//!
+//! [surface]
+//! allowed = ["paved", "asphalt", "concrete", "paving_stones"]
+//! allow_untagged = true
+//!
+//! [speed]  # km/h
+//! default = 15
+//! highway = { primary = 18, cycleway = 20 }
+//!
+//! [factors]  # multiply the travel time, > 1 avoids and < 1 prefers
+//! surface = { paving_stones = 1.5 }
+//! smoothness = { bad = 1.5 }
+//! cycleway = 0.8
+//! lit = 0.95
+//!
+//! [elevation]
+//! climb_penalty = 8  # seconds per meter of ascent
+//!
+//! [restrictions]
+//! enabled = true
+//! vehicles = ["bicycle"]  # matched against `except` and `restriction:<vehicle>`
+//!
+//! [turns]  # seconds, routes edge by edge when present
+//! u_turn = 20
+//! sharp_turn = 5
+//! sharp_angle = 120  # degrees off straight on
+//! major_crossing = 6
+//! major_highways = ["trunk", "primary", "secondary"]
+//! ```
+//!
+//! The cost of an edge is its travel time in seconds at the speed of its
+//! `highway` value, multiplied by every factor that applies to the way, plus
+//! the climb penalty when the graph is built with elevation data. Turn costs
+//! are added by the routers when passing a node.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    ops::Range,
+    path::Path,
+};
+
+use osmpbfreader::Tags;
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::{error::Error, fingerprint::fnv1a, restriction::RestrictionKind};
+
+const BICYCLE_PROFILE: &str = include_str!("../profiles/bicycle.toml");
+
+/// Whether edges are only added in the direction of `oneway` ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnewayMode {
+    Respect,
+    Ignore,
+}
+
+/// Directions in which a way can be travelled, relative to the order of its nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Both,
+    Forward,
+    Backward,
+    /// Neither, e.g. `oneway=reversible` ways whose direction changes over time.
+    Closed,
+}
+
+/// A validated routing profile.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Profile {
+    pub name: String,
+    pub oneway: OnewayMode,
+    /// Oneway keys from the most general to the most specific, e.g.
+    /// `["oneway", "oneway:bicycle"]`. The most specific key a way has decides.
+    pub oneway_keys: Vec<String>,
+    /// Whether `cycleway=opposite*` lanes make a oneway way passable both ways.
+    pub cycleway_contraflow: bool,
+    /// Accepted `highway` values, or every value if empty.
+    pub allowed_highways: BTreeSet<String>,
+    pub forbidden_highways: BTreeSet<String>,
+    /// Access keys from the most general to the most specific, e.g.
+    /// `["access", "vehicle", "bicycle"]`. The most specific key a way has decides.
+    pub access_keys: Vec<String>,
+    pub forbidden_access: BTreeSet<String>,
+    /// Accepted `surface` values, or every value if empty.
+    pub allowed_surfaces: BTreeSet<String>,
+    pub allow_untagged_surface: bool,
+    /// Travel speed in km/h by `highway` value.
+    pub highway_speeds: BTreeMap<String, f64>,
+    /// Travel speed in km/h of `highway` values missing from `highway_speeds`.
+    pub default_speed: f64,
+    pub surface_factors: BTreeMap<String, f64>,
+    pub smoothness_factors: BTreeMap<String, f64>,
+    /// Applied to ways with a cycle lane or track.
+    pub cycleway_factor: f64,
+    /// Applied to ways tagged `lit=yes`.
+    pub lit_factor: f64,
+    /// Seconds added per meter of ascent.
+    pub climb_penalty: f64,
+    /// Whether turn restriction relations are honored.
+    pub turn_restrictions: bool,
+    /// Vehicle names of `except` and `restriction:<vehicle>` tags that apply.
+    pub vehicles: Vec<String>,
+    /// Penalties for turning at nodes, if routing edge by edge.
+    pub turn_costs: Option<TurnCosts>,
+    /// Strongly connected components of fewer nodes are dropped from the graph.
+    pub min_component_size: usize,
+}
+
+/// Penalties in seconds for the turns at a node.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TurnCosts {
+    /// Going back the way the route came.
+    pub u_turn: f64,
+    /// Turning more than `sharp_angle` degrees off straight on.
+    pub sharp_turn: f64,
+    pub sharp_angle: f64,
+    /// Passing a node of a major road while not following it.
+    pub major_crossing: f64,
+    /// `highway` values of the major roads.
+    pub major_highways: BTreeSet<String>,
+}
+
+impl TurnCosts {
+    /// Penalty of a turn `angle` degrees off straight on, which is a U-turn or
+    /// crosses a major road as told.
+    pub fn cost(&self, angle: f64, is_u_turn: bool, crosses_major: bool) -> f64 {
+        let mut cost = if is_u_turn {
+            self.u_turn
+        } else if angle > self.sharp_angle {
+            self.sharp_turn
+        } else {
+            0.0
+        };
+        if crosses_major {
+            cost += self.major_crossing;
+        }
+        cost
+    }
+}
+
+/// A profile file that failed to parse or validate.
+#[derive(Debug)]
+pub struct ProfileError {
+    pub source_name: String,
+    /// 1-based line of the offending value, if known.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.source_name, line, self.message),
+            None => write!(f, "{}: {}", self.source_name, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfile {
+    name: String,
+    oneway: OnewayMode,
+    #[serde(default = "default_oneway_keys")]
+    oneway_keys: Vec<Spanned<String>>,
+    #[serde(default)]
+    cycleway_contraflow: bool,
+    #[serde(default)]
+    min_component_size: usize,
+    #[serde(default)]
+    highway: RawHighway,
+    #[serde(default)]
+    access: RawAccess,
+    #[serde(default)]
+    surface: RawSurface,
+    speed: RawSpeed,
+    #[serde(default)]
+    factors: RawFactors,
+    #[serde(default)]
+    elevation: RawElevation,
+    #[serde(default)]
+    restrictions: RawRestrictions,
+    turns: Option<RawTurns>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawHighway {
+    #[serde(default)]
+    allowed: Vec<Spanned<String>>,
+    #[serde(default)]
+    forbidden: Vec<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawAccess {
+    #[serde(default = "default_access_keys")]
+    keys: Vec<Spanned<String>>,
+    #[serde(default)]
+    forbidden: Vec<Spanned<String>>,
+}
+
+impl Default for RawAccess {
+    fn default() -> Self {
+        RawAccess {
+            keys: default_access_keys(),
+            forbidden: Vec::new(),
+        }
+    }
+}
+
+fn default_access_keys() -> Vec<Spanned<String>> {
+    vec![Spanned::new(0..0, "access".to_string())]
+}
+
+fn default_oneway_keys() -> Vec<Spanned<String>> {
+    vec![Spanned::new(0..0, "oneway".to_string())]
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSurface {
+    #[serde(default)]
+    allowed: Vec<Spanned<String>>,
+    #[serde(default = "default_true")]
+    allow_untagged: bool,
+}
+
+impl Default for RawSurface {
+    fn default() -> Self {
+        RawSurface {
+            allowed: Vec::new(),
+            allow_untagged: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSpeed {
+    default: Spanned<f64>,
+    #[serde(default)]
+    highway: BTreeMap<String, Spanned<f64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawFactors {
+    #[serde(default)]
+    surface: BTreeMap<String, Spanned<f64>>,
+    #[serde(default)]
+    smoothness: BTreeMap<String, Spanned<f64>>,
+    cycleway: Option<Spanned<f64>>,
+    lit: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawElevation {
+    climb_penalty: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRestrictions {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default)]
+    vehicles: Vec<Spanned<String>>,
+}
+
+impl Default for RawRestrictions {
+    fn default() -> Self {
+        RawRestrictions {
+            enabled: true,
+            vehicles: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTurns {
+    u_turn: Option<Spanned<f64>>,
+    sharp_turn: Option<Spanned<f64>>,
+    sharp_angle: Option<Spanned<f64>>,
+    major_crossing: Option<Spanned<f64>>,
+    #[serde(default)]
+    major_highways: Vec<Spanned<String>>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Profile {
+    /// The built-in bicycle profile, identical to `profiles/bicycle.toml`.
+    pub fn bicycle() -> Self {
+        Self::parse(BICYCLE_PROFILE, "profiles/bicycle.toml").expect("built-in profile is valid")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Ok(Self::parse(&text, &path.display().to_string())?)
+    }
+
+    /// Parses and validates a profile. `source_name` is only used in errors.
+    pub fn parse(text: &str, source_name: &str) -> Result<Self, ProfileError> {
+        let error = |span: Option<Range<usize>>, message: String| ProfileError {
+            source_name: source_name.to_string(),
+            line: span.map(|span| line_of(text, span.start)),
+            message,
+        };
+
+        let raw: RawProfile =
+            toml::from_str(text).map_err(|e| error(e.span(), e.message().to_string()))?;
+        if raw.name.is_empty()
+            || !raw
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(error(
+                None,
+                format!(
+                    "`name` must be non-empty ASCII letters, digits, `-` or `_`, got `{}`",
+                    raw.name
+                ),
+            ));
+        }
+
+        let to_set = |values: &[Spanned<String>], what: &str| {
+            let mut set = BTreeSet::new();
+            for value in values {
+                if value.get_ref().is_empty() {
+                    return Err(error(Some(value.span()), format!("empty {}", what)));
+                }
+                if !set.insert(value.get_ref().clone()) {
+                    return Err(error(
+                        Some(value.span()),
+                        format!("duplicated {} `{}`", what, value.get_ref()),
+                    ));
+                }
+            }
+            Ok(set)
+        };
+
+        let allowed_highways = to_set(&raw.highway.allowed, "highway value")?;
+        let forbidden_highways = to_set(&raw.highway.forbidden, "highway value")?;
+        if let Some(value) = raw
+            .highway
+            .forbidden
+            .iter()
+            .find(|value| allowed_highways.contains(value.get_ref()))
+        {
+            return Err(error(
+                Some(value.span()),
+                format!(
+                    "highway `{}` is both allowed and forbidden",
+                    value.get_ref()
+                ),
+            ));
+        }
+        let access_keys = to_set(&raw.access.keys, "access key")?;
+        if access_keys.is_empty() {
+            return Err(error(None, "`access.keys` must not be empty".to_string()));
+        }
+        if to_set(&raw.oneway_keys, "oneway key")?.is_empty() {
+            return Err(error(None, "`oneway_keys` must not be empty".to_string()));
+        }
+
+        let positive = |value: &Spanned<f64>, what: &str| {
+            if value.get_ref().is_finite() && *value.get_ref() > 0.0 {
+                Ok(*value.get_ref())
+            } else {
+                Err(error(
+                    Some(value.span()),
+                    format!(
+                        "{} must be a positive number, got {}",
+                        what,
+                        value.get_ref()
+                    ),
+                ))
+            }
+        };
+        let non_negative = |value: &Option<Spanned<f64>>, what: &str, default: f64| match value {
+            Some(value) if !(value.get_ref().is_finite() && *value.get_ref() >= 0.0) => Err(error(
+                Some(value.span()),
+                format!(
+                    "{} must be a non-negative number, got {}",
+                    what,
+                    value.get_ref()
+                ),
+            )),
+            Some(value) => Ok(*value.get_ref()),
+            None => Ok(default),
+        };
+        let to_map = |values: &BTreeMap<String, Spanned<f64>>, what: &str| {
+            values
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), positive(value, what)?)))
+                .collect::<Result<BTreeMap<_, _>, _>>()
+        };
+
+        Ok(Profile {
+            highway_speeds: to_map(&raw.speed.highway, "speed")?,
+            default_speed: positive(&raw.speed.default, "speed")?,
+            surface_factors: to_map(&raw.factors.surface, "factor")?,
+            smoothness_factors: to_map(&raw.factors.smoothness, "factor")?,
+            cycleway_factor: raw
+                .factors
+                .cycleway
+                .as_ref()
+                .map_or(Ok(1.0), |value| positive(value, "factor"))?,
+            lit_factor: raw
+                .factors
+                .lit
+                .as_ref()
+                .map_or(Ok(1.0), |value| positive(value, "factor"))?,
+            climb_penalty: non_negative(&raw.elevation.climb_penalty, "climb_penalty", 0.0)?,
+            turn_costs: match &raw.turns {
+                Some(turns) => Some(TurnCosts {
+                    u_turn: non_negative(&turns.u_turn, "u_turn", 0.0)?,
+                    sharp_turn: non_negative(&turns.sharp_turn, "sharp_turn", 0.0)?,
+                    sharp_angle: match non_negative(&turns.sharp_angle, "sharp_angle", 120.0)? {
+                        angle if angle > 180.0 => {
+                            return Err(error(
+                                turns.sharp_angle.as_ref().map(Spanned::span),
+                                format!("sharp_angle must be at most 180, got {}", angle),
+                            ))
+                        }
+                        angle => angle,
+                    },
+                    major_crossing: non_negative(&turns.major_crossing, "major_crossing", 0.0)?,
+                    major_highways: to_set(&turns.major_highways, "highway value")?,
+                }),
+                None => None,
+            },
+            name: raw.name,
+            oneway: raw.oneway,
+            oneway_keys: raw
+                .oneway_keys
+                .into_iter()
+                .map(Spanned::into_inner)
+                .collect(),
+            cycleway_contraflow: raw.cycleway_contraflow,
+            min_component_size: raw.min_component_size,
+            allowed_highways,
+            forbidden_highways,
+            access_keys: raw
+                .access
+                .keys
+                .into_iter()
+                .map(Spanned::into_inner)
+                .collect(),
+            forbidden_access: to_set(&raw.access.forbidden, "access value")?,
+            allowed_surfaces: to_set(&raw.surface.allowed, "surface")?,
+            allow_untagged_surface: raw.surface.allow_untagged,
+            turn_restrictions: raw.restrictions.enabled,
+            vehicles: to_set(&raw.restrictions.vehicles, "vehicle")?
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    /// Hash of the rules, independent of formatting and comments of the file.
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(
+            toml::to_string(self)
+                .expect("profiles are serializable")
+                .bytes(),
+        )
+    }
+
+    /// Whether a way with `tags` belongs in the graph.
+    pub fn accepts(&self, tags: &Tags) -> bool {
+        let Some(highway) = tags.get("highway") else {
+            return false;
+        };
+        let is_allowed_highway = (self.allowed_highways.is_empty()
+            || self.allowed_highways.contains(highway.as_str()))
+            && !self.forbidden_highways.contains(highway.as_str());
+
+        let is_paved = match tags.get("surface") {
+            Some(surface) => {
+                self.allowed_surfaces.is_empty() || self.allowed_surfaces.contains(surface.as_str())
+            }
+            None => self.allow_untagged_surface,
+        };
+
+        let is_accessible = self
+            .access_keys
+            .iter()
+            .rev()
+            .find_map(|key| tags.get(key.as_str()))
+            .is_none_or(|value| !self.forbidden_access.contains(value.as_str()));
+
+        is_allowed_highway && is_paved && is_accessible
+    }
+
+    /// Directions in which a way with `tags` can be travelled.
+    ///
+    /// `oneway=yes/true/1` and `junction=roundabout` without a oneway tag only
+    /// allow the direction of the way, `oneway=-1` only the opposite one, and
+    /// `oneway=reversible/alternating` neither.
+    pub fn direction(&self, tags: &Tags) -> Direction {
+        if self.oneway == OnewayMode::Ignore {
+            return Direction::Both;
+        }
+
+        let oneway = self
+            .oneway_keys
+            .iter()
+            .rev()
+            .find_map(|key| tags.get(key.as_str()));
+        let direction = match oneway.map(|value| value.as_str()) {
+            Some("yes" | "true" | "1") => Direction::Forward,
+            Some("-1" | "reverse") => Direction::Backward,
+            Some("reversible" | "alternating") => Direction::Closed,
+            Some(_) => Direction::Both,
+            None if tags.contains("junction", "roundabout") => Direction::Forward,
+            None => Direction::Both,
+        };
+
+        match direction {
+            Direction::Forward | Direction::Backward
+                if self.cycleway_contraflow && has_contraflow_cycleway(tags) =>
+            {
+                Direction::Both
+            }
+            direction => direction,
+        }
+    }
+
+    /// Lower bound of the cost per meter of any edge, i.e. the inverse of the
+    /// best speed with every factor below 1 applied.
+    pub fn min_cost_per_meter(&self) -> f64 {
+        let max_speed = self
+            .highway_speeds
+            .values()
+            .copied()
+            .fold(self.default_speed, f64::max);
+        let min_factor =
+            |factors: &BTreeMap<String, f64>| factors.values().copied().fold(1.0, f64::min);
+        min_factor(&self.surface_factors)
+            * min_factor(&self.smoothness_factors)
+            * self.cycleway_factor.min(1.0)
+            * self.lit_factor.min(1.0)
+            / (max_speed / 3.6)
+    }
+
+    /// Whether a way with `tags` is a major road for the turn costs.
+    pub fn is_major(&self, tags: &Tags) -> bool {
+        self.turn_costs.as_ref().is_some_and(|turn_costs| {
+            tags.get("highway")
+                .is_some_and(|highway| turn_costs.major_highways.contains(highway.as_str()))
+        })
+    }
+
+    /// Kind of the turn restriction a relation with `tags` imposes, or `None`
+    /// if it doesn't apply to the vehicles of the profile.
+    pub fn restriction(&self, tags: &Tags) -> Option<RestrictionKind> {
+        if !self.turn_restrictions || !tags.contains("type", "restriction") {
+            return None;
+        }
+        if let Some(except) = tags.get("except") {
+            if except
+                .split(';')
+                .any(|vehicle| self.vehicles.iter().any(|v| v == vehicle.trim()))
+            {
+                return None;
+            }
+        }
+        self.vehicles
+            .iter()
+            .find_map(|vehicle| tags.get(format!("restriction:{}", vehicle).as_str()))
+            .or_else(|| tags.get("restriction"))
+            .and_then(|value| RestrictionKind::parse(value))
+    }
+
+    /// Travel time in seconds along `length` meters of a way with `tags`,
+    /// weighted by the factors of the profile.
+    pub fn edge_cost(&self, tags: &Tags, length: f64) -> f64 {
+        let speed = tags
+            .get("highway")
+            .and_then(|highway| self.highway_speeds.get(highway.as_str()))
+            .copied()
+            .unwrap_or(self.default_speed);
+
+        let mut factor = 1.0;
+        if let Some(f) = tags
+            .get("surface")
+            .and_then(|surface| self.surface_factors.get(surface.as_str()))
+        {
+            factor *= f;
+        }
+        if let Some(f) = tags
+            .get("smoothness")
+            .and_then(|smoothness| self.smoothness_factors.get(smoothness.as_str()))
+        {
+            factor *= f;
+        }
+        if has_cycleway(tags) {
+            factor *= self.cycleway_factor;
+        }
+        if tags.contains("lit", "yes") {
+            factor *= self.lit_factor;
+        }
+
+        length / (speed / 3.6) * factor
+    }
+}
+
+fn has_cycleway(tags: &Tags) -> bool {
+    [
+        "cycleway",
+        "cycleway:both",
+        "cycleway:left",
+        "cycleway:right",
+    ]
+    .iter()
+    .filter_map(|key| tags.get(*key))
+    .any(|value| value != "no" && value != "none" && value != "separate")
+}
+
+fn has_contraflow_cycleway(tags: &Tags) -> bool {
+    [
+        "cycleway",
+        "cycleway:both",
+        "cycleway:left",
+        "cycleway:right",
+    ]
+    .iter()
+    .filter_map(|key| tags.get(*key))
+    .any(|value| value.starts_with("opposite"))
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = "name = \"test\"\noneway = \"respect\"\n\n[speed]\ndefault = 15\n";
+
+    /// Parses `MINIMAL` with `extra` appended, which starts at line 6.
+    fn parse_with(extra: &str) -> Result<Profile, ProfileError> {
+        Profile::parse(&format!("{}{}", MINIMAL, extra), "test.toml")
+    }
+
+    #[test]
+    fn shipped_profiles_are_valid() {
+        for (text, name) in [
+            (BICYCLE_PROFILE, "bicycle.toml"),
+            (include_str!("../profiles/car.toml"), "car.toml"),
+            (include_str!("../profiles/foot.toml"), "foot.toml"),
+            (include_str!("../profiles/road-bike.toml"), "road-bike.toml"),
+        ] {
+            Profile::parse(text, name).unwrap();
+        }
+        assert_eq!(parse_with("").unwrap().name, "test");
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_at_their_line() {
+        let err = parse_with("cyclewy_contraflow = true\n").unwrap_err();
+        assert_eq!(err.line, Some(6));
+        assert!(err.message.contains("cyclewy_contraflow"), "{}", err);
+
+        let err = parse_with("[surface]\nallow_untaged = false\n").unwrap_err();
+        assert_eq!(err.line, Some(7));
+        assert!(err.message.contains("allow_untaged"), "{}", err);
+        assert!(err.to_string().starts_with("test.toml:7: "), "{}", err);
+    }
+
+    #[test]
+    fn bad_values_are_reported_at_their_line() {
+        for (extra, line, message) in [
+            (
+                "[factors]\nlit = 0\n",
+                7,
+                "factor must be a positive number, got 0",
+            ),
+            (
+                "[elevation]\nclimb_penalty = -1\n",
+                7,
+                "must be a non-negative number",
+            ),
+            (
+                "[turns]\n\nsharp_angle = 200\n",
+                8,
+                "sharp_angle must be at most 180",
+            ),
+            (
+                "[highway]\nallowed = [\"primary\"]\nforbidden = [\"primary\"]\n",
+                8,
+                "highway `primary` is both allowed and forbidden",
+            ),
+            (
+                "[access]\nforbidden = [\"no\", \"no\"]\n",
+                7,
+                "duplicated access value `no`",
+            ),
+            ("[surface]\nallowed = [\"\"]\n", 7, "empty surface"),
+        ] {
+            let err = parse_with(extra).unwrap_err();
+            assert_eq!(err.line, Some(line), "{}", err);
+            assert!(err.message.contains(message), "{}", err);
+        }
+
+        let err = Profile::parse(&MINIMAL.replace("15", "\"fast\""), "test.toml").unwrap_err();
+        assert_eq!(err.line, Some(5));
+        let err = Profile::parse(&MINIMAL.replace("15", "-15"), "test.toml").unwrap_err();
+        assert_eq!(err.line, Some(5));
+        assert!(err.message.contains("speed must be a positive number"));
+    }
+
+    #[test]
+    fn errors_without_a_value_have_no_line() {
+        let err = Profile::parse(&MINIMAL.replace("test", "a b"), "test.toml").unwrap_err();
+        assert_eq!(err.line, None);
+        assert!(
+            err.to_string().starts_with("test.toml: `name` must be"),
+            "{}",
+            err
+        );
+
+        let err = parse_with("[access]\nkeys = []\n").unwrap_err();
+        assert_eq!(err.line, None);
+        assert!(err.message.contains("`access.keys` must not be empty"));
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs.iter().map(|&(k, v)| (k.into(), v.into())).collect()
+    }
+
+    #[test]
+    fn edges_cost_their_travel_time_at_the_highway_speed() {
+        let profile = Profile::bicycle();
+        // 15 km/h by default, and 16 km/h on primary roads.
+        let cost = profile.edge_cost(&tags(&[("highway", "residential")]), 1000.0);
+        assert!((cost - 240.0).abs() < 1e-9, "{}", cost);
+        let cost = profile.edge_cost(&tags(&[("highway", "primary")]), 1000.0);
+        assert!((cost - 225.0).abs() < 1e-9, "{}", cost);
+    }
+
+    #[test]
+    fn penalty_factors_multiply_the_travel_time() {
+        let profile = Profile::bicycle();
+        let plain = profile.edge_cost(&tags(&[("highway", "residential")]), 100.0);
+        let weighted = profile.edge_cost(
+            &tags(&[
+                ("highway", "residential"),
+                ("surface", "paving_stones"),
+                ("smoothness", "bad"),
+                ("cycleway", "lane"),
+                ("lit", "yes"),
+            ]),
+            100.0,
+        );
+        let factor = 1.3 * 1.5 * 0.85 * 0.95;
+        assert!((weighted - plain * factor).abs() < 1e-9, "{}", weighted);
+
+        for (key, value) in [
+            ("cycleway", "no"),
+            ("cycleway:left", "separate"),
+            ("lit", "no"),
+            ("surface", "asphalt"),
+        ] {
+            let cost = profile.edge_cost(&tags(&[("highway", "residential"), (key, value)]), 100.0);
+            assert_eq!(cost, plain, "{}={}", key, value);
+        }
+    }
+
+    #[test]
+    fn min_cost_per_meter_is_a_lower_bound() {
+        let profile = Profile::bicycle();
+        let min = profile.min_cost_per_meter();
+        let highways = profile
+            .highway_speeds
+            .keys()
+            .map(String::as_str)
+            .chain(["residential"])
+            .collect::<Vec<_>>();
+        fn optional(factors: &BTreeMap<String, f64>) -> Vec<Option<&str>> {
+            factors
+                .keys()
+                .map(|key| Some(key.as_str()))
+                .chain([None])
+                .collect()
+        }
+        let mut lowest = f64::INFINITY;
+        for highway in highways {
+            for surface in optional(&profile.surface_factors) {
+                for smoothness in optional(&profile.smoothness_factors) {
+                    for (cycleway, lit) in
+                        [("no", "no"), ("lane", "no"), ("no", "yes"), ("lane", "yes")]
+                    {
+                        let mut pairs =
+                            vec![("highway", highway), ("cycleway", cycleway), ("lit", lit)];
+                        pairs.extend(surface.map(|value| ("surface", value)));
+                        pairs.extend(smoothness.map(|value| ("smoothness", value)));
+                        let cost_per_meter = profile.edge_cost(&tags(&pairs), 250.0) / 250.0;
+                        assert!(cost_per_meter >= min - 1e-12, "{:?}", pairs);
+                        lowest = lowest.min(cost_per_meter);
+                    }
+                }
+            }
+        }
+        // The bound is reached by the fastest combination, so A* loses nothing.
+        assert!((lowest - min).abs() < 1e-12, "{} > {}", lowest, min);
+    }
+}
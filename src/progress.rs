@@ -0,0 +1,71 @@
+//! Progress bars and status lines for the long running steps, written to
+//! stderr unless quiet.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+
+/// Searches settle millions of states a second, so the count is only passed
+/// on to the bar this often.
+const SETTLED_STEP: usize = 4096;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Hides the progress bars created and the status lines printed from now on,
+/// e.g. for scripting.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Prints a line about how a step went, such as what it built and how long it
+/// took.
+pub fn status(line: fmt::Arguments) {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("{}", line);
+    }
+}
+
+fn bar(len: Option<u64>, template: &str, message: &'static str) -> ProgressBar {
+    if QUIET.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    let bar = match len {
+        Some(len) => ProgressBar::new(len),
+        None => ProgressBar::new_spinner(),
+    };
+    bar.with_style(ProgressStyle::with_template(template).unwrap())
+        .with_message(message)
+        .with_finish(ProgressFinish::AndClear)
+}
+
+/// A bar over `len` bytes, or a count of them if the length is unknown.
+pub(crate) fn bytes(message: &'static str, len: Option<u64>) -> ProgressBar {
+    let template = match len {
+        Some(_) => "{msg} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+        None => "{spinner} {msg} {bytes} ({bytes_per_sec})",
+    };
+    bar(len, template, message)
+}
+
+/// A bar over `len` items, such as ways.
+pub(crate) fn items(message: &'static str, len: usize) -> ProgressBar {
+    bar(Some(len as u64), "{msg} [{bar:40}] {pos}/{len}", message)
+}
+
+/// Count of the states a search has settled, cleared when it returns.
+pub(crate) struct Settled(ProgressBar);
+
+impl Settled {
+    pub(crate) fn new() -> Self {
+        Settled(bar(None, "{spinner} Searching: {pos} states settled", ""))
+    }
+
+    pub(crate) fn update(&self, settled: usize) {
+        if settled.is_multiple_of(SETTLED_STEP) {
+            self.0.set_position(settled as u64);
+        }
+    }
+}
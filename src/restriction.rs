@@ -0,0 +1,291 @@
+//! Turn restrictions from `type=restriction` relations.
+//!
+//! A restriction is resolved to the sequence of nodes it covers: the node
+//! before the via node(s) on the `from` way, the via node or the nodes of the
+//! via way, and the node after them on the `to` way. The routers track how much
+//! of each sequence the current path has followed in [`TurnRestrictions`].
+
+use std::collections::{HashMap, VecDeque};
+
+use osmpbfreader::{NodeId, OsmId, Relation, Way, WayId};
+
+use crate::graph::NodeIdx;
+
+/// Whether a restriction forbids its turn or makes it the only one allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestrictionKind {
+    /// `no_left_turn`, `no_u_turn`, ...
+    No,
+    /// `only_straight_on`, `only_right_turn`, ...
+    Only,
+}
+
+impl RestrictionKind {
+    /// Kind of a `restriction` tag value, or `None` if it's not a turn restriction.
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.starts_with("no_") {
+            Some(RestrictionKind::No)
+        } else if value.starts_with("only_") {
+            Some(RestrictionKind::Only)
+        } else {
+            None
+        }
+    }
+}
+
+/// A turn restriction resolved to OSM nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnRestriction {
+    pub kind: RestrictionKind,
+    /// From node, via nodes in the order they are passed, then the to node.
+    pub nodes: Vec<NodeId>,
+}
+
+impl TurnRestriction {
+    /// Resolves the members of `relation` against `ways`.
+    ///
+    /// Returns `Ok(None)` if a member way isn't in `ways`, i.e. it is not part
+    /// of the graph and the restriction can't matter, and an error if the
+    /// members don't form a valid restriction.
+    pub fn resolve(
+        relation: &Relation,
+        kind: RestrictionKind,
+        ways: &HashMap<WayId, &Way>,
+    ) -> Result<Option<Self>, String> {
+        let mut from = None;
+        let mut to = None;
+        let mut via_node = None;
+        let mut via_ways = Vec::new();
+        for member in &relation.refs {
+            match (member.role.as_str(), member.member) {
+                ("from", OsmId::Way(id)) if from.is_none() => from = Some(id),
+                ("to", OsmId::Way(id)) if to.is_none() => to = Some(id),
+                ("via", OsmId::Node(id)) if via_node.is_none() => via_node = Some(id),
+                ("via", OsmId::Way(id)) => via_ways.push(id),
+                ("from" | "to" | "via", _) => {
+                    return Err(format!(
+                        "unexpected {} member {:?}",
+                        member.role, member.member
+                    ))
+                }
+                _ => {}
+            }
+        }
+        let (Some(from), Some(to)) = (from, to) else {
+            return Err("missing from or to way".to_string());
+        };
+
+        let mut via = match (via_node, via_ways.as_slice()) {
+            (Some(node), []) => vec![node],
+            (None, &[via_way]) => {
+                let Some(via_way) = ways.get(&via_way) else {
+                    return Ok(None);
+                };
+                via_way.nodes.clone()
+            }
+            (None, []) => return Err("missing via member".to_string()),
+            _ => return Err("only a single via node or via way is supported".to_string()),
+        };
+        let (Some(from), Some(to)) = (ways.get(&from), ways.get(&to)) else {
+            return Ok(None);
+        };
+
+        // Orient the via nodes so that they start where the from way ends.
+        if via.len() > 1 && neighbor_at_end(from, via[0]).is_none() {
+            via.reverse();
+        }
+        let (first, last) = (via[0], via[via.len() - 1]);
+        let (Some(from_node), Some(to_node)) =
+            (neighbor_at_end(from, first), neighbor_at_end(to, last))
+        else {
+            return Err("from and to ways don't end at the via member".to_string());
+        };
+
+        let mut nodes = Vec::with_capacity(via.len() + 2);
+        nodes.push(from_node);
+        nodes.extend(via);
+        nodes.push(to_node);
+        Ok(Some(TurnRestriction { kind, nodes }))
+    }
+}
+
+/// The node next to `node` on `way`, if `way` starts or ends at `node`.
+fn neighbor_at_end(way: &Way, node: NodeId) -> Option<NodeId> {
+    match way.nodes.as_slice() {
+        [first, second, ..] if *first == node => Some(*second),
+        [.., second_last, last] if *last == node => Some(*second_last),
+        _ => None,
+    }
+}
+
+/// Turn restrictions as a trie of their node index sequences, which routers
+/// step through edge by edge.
+///
+/// A search state pairs a node with a position in the trie, the longest
+/// restriction prefix its path ends with. Position `0` is the empty prefix, so
+/// without restrictions every state is `(node, 0)`. As in Aho–Corasick, every
+/// position links to the longest shorter prefix its path also ends with, so
+/// that a restriction starting inside another one is followed too.
+#[derive(Debug, Clone, Default)]
+pub struct TurnRestrictions {
+    /// Positions after the first edge of a restriction.
+    starts: HashMap<(NodeIdx, NodeIdx), u32>,
+    /// Trie nodes, the first one being the unused empty prefix.
+    trie: Vec<TrieNode>,
+    has_via_ways: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: HashMap<NodeIdx, u32>,
+    /// Position of the longest proper suffix of this prefix in the trie, or
+    /// `0` if there is none.
+    fail: u32,
+    /// Reaching this position completes a `no_*` restriction.
+    forbidden: bool,
+    /// The next node an `only_*` restriction ending here requires.
+    only: Option<NodeIdx>,
+}
+
+impl TurnRestrictions {
+    /// Builds the trie of `restrictions`, with their nodes numbered by
+    /// `index` and leaving out those with a node it doesn't know.
+    pub fn new(
+        restrictions: &[TurnRestriction],
+        index: impl Fn(NodeId) -> Option<NodeIdx>,
+    ) -> Self {
+        let mut this = TurnRestrictions {
+            starts: HashMap::new(),
+            trie: vec![TrieNode::default()],
+            has_via_ways: false,
+        };
+        for restriction in restrictions {
+            let Some(nodes) = restriction
+                .nodes
+                .iter()
+                .map(|&id| index(id))
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            let [from, via @ .., to] = nodes.as_slice() else {
+                continue;
+            };
+            if via.is_empty() {
+                continue;
+            }
+            this.has_via_ways |= via.len() > 1;
+            let mut pos = match this.starts.get(&(*from, via[0])) {
+                Some(&pos) => pos,
+                None => {
+                    let pos = this.push();
+                    this.starts.insert((*from, via[0]), pos);
+                    pos
+                }
+            };
+            for &node in &via[1..] {
+                pos = this.child(pos, node);
+            }
+            match restriction.kind {
+                RestrictionKind::No => {
+                    let pos = this.child(pos, *to);
+                    this.trie[pos as usize].forbidden = true;
+                }
+                RestrictionKind::Only => {
+                    this.trie[pos as usize].only.get_or_insert(*to);
+                }
+            }
+        }
+        this.link();
+        this
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Whether some restriction has a via way, so that turns can't be
+    /// checked by [`TurnRestrictions::allows`] alone.
+    pub fn has_via_ways(&self) -> bool {
+        self.has_via_ways
+    }
+
+    /// Whether going from `from` through `via` to `to` is allowed by the
+    /// restrictions with a via node.
+    pub fn allows(&self, from: NodeIdx, via: NodeIdx, to: NodeIdx) -> bool {
+        self.step(0, from, via)
+            .is_some_and(|pos| self.step(pos, via, to).is_some())
+    }
+
+    /// The position after moving from `from` to `to` at position `pos`, or
+    /// `None` if a restriction forbids it.
+    pub fn step(&self, pos: u32, from: NodeIdx, to: NodeIdx) -> Option<u32> {
+        if self
+            .suffixes(pos)
+            .any(|node| node.only.is_some_and(|only| only != to))
+        {
+            return None;
+        }
+        let next = self.next(pos, from, to);
+        if self.suffixes(next).any(|node| node.forbidden) {
+            return None;
+        }
+        Some(next)
+    }
+
+    /// The longest prefix a path at `pos` ends with after moving from `from`
+    /// to `to`.
+    fn next(&self, mut pos: u32, from: NodeIdx, to: NodeIdx) -> u32 {
+        while pos != 0 {
+            let node = &self.trie[pos as usize];
+            if let Some(&child) = node.children.get(&to) {
+                return child;
+            }
+            pos = node.fail;
+        }
+        self.starts.get(&(from, to)).copied().unwrap_or(0)
+    }
+
+    /// The prefixes a path at `pos` ends with, from the longest.
+    fn suffixes(&self, pos: u32) -> impl Iterator<Item = &TrieNode> {
+        let known = |pos: u32| (pos != 0).then_some(pos);
+        std::iter::successors(known(pos), move |&pos| known(self.trie[pos as usize].fail))
+            .map(|pos| &self.trie[pos as usize])
+    }
+
+    /// Sets the failure links breadth first, so that those of shorter
+    /// prefixes are known when following them.
+    fn link(&mut self) {
+        let mut queue = self
+            .starts
+            .iter()
+            .map(|(&(_, to), &pos)| (pos, to))
+            .collect::<VecDeque<_>>();
+        while let Some((pos, last)) = queue.pop_front() {
+            let children = self.trie[pos as usize]
+                .children
+                .iter()
+                .map(|(&node, &child)| (node, child))
+                .collect::<Vec<_>>();
+            for (node, child) in children {
+                let fail = self.next(self.trie[pos as usize].fail, last, node);
+                self.trie[child as usize].fail = fail;
+                queue.push_back((child, node));
+            }
+        }
+    }
+
+    fn push(&mut self) -> u32 {
+        self.trie.push(TrieNode::default());
+        (self.trie.len() - 1) as u32
+    }
+
+    fn child(&mut self, pos: u32, node: NodeIdx) -> u32 {
+        if let Some(&child) = self.trie[pos as usize].children.get(&node) {
+            return child;
+        }
+        let child = self.push();
+        self.trie[pos as usize].children.insert(node, child);
+        child
+    }
+}
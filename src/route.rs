@@ -0,0 +1,176 @@
+use geo::{coord, point, Coord, HaversineDistance};
+use itertools::Itertools;
+use osmpbfreader::NodeId;
+
+use crate::graph::{NodeIdx, RoadGraph};
+
+/// A path found by a [`Router`](crate::Router).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    /// Total length in meters.
+    pub distance: f64,
+    /// Total cost of the edges, see [`Profile::edge_cost`](crate::Profile::edge_cost).
+    pub cost: f64,
+    /// Visited junctions, from the start to the goal, see [`Route::path`].
+    pub nodes: Vec<NodeIdx>,
+    /// Number of search states the router settled to find the route.
+    pub settled: usize,
+}
+
+impl Route {
+    /// Every node of the route, including those passed between junctions
+    /// along the edges that [collapse chains](RoadGraph::collapse_chains).
+    pub fn path(&self, graph: &RoadGraph) -> Vec<NodeIdx> {
+        let mut path = self.nodes[..1.min(self.nodes.len())].to_vec();
+        for (&u, &v) in self.nodes.iter().tuple_windows() {
+            path.extend(graph.geometry(u, v).iter().map(|via| via.node));
+            path.push(v);
+        }
+        path
+    }
+
+    /// OSM IDs of the nodes of [`Route::path`].
+    pub fn node_ids(&self, graph: &RoadGraph) -> Vec<NodeId> {
+        self.path(graph)
+            .into_iter()
+            .map(|idx| graph.id_of(idx))
+            .collect()
+    }
+
+    pub fn coords(&self, graph: &RoadGraph) -> Vec<Coord> {
+        self.path(graph)
+            .iter()
+            .filter_map(|&idx| graph.position(idx))
+            .map(|position| coord! { x: position.lon(), y: position.lat() })
+            .collect()
+    }
+
+    /// Encodes the route as a polyline with 5 digits of precision.
+    pub fn to_polyline(&self, graph: &RoadGraph) -> Result<String, String> {
+        polyline::encode_coordinates(self.coords(graph), 5)
+    }
+
+    /// Total ascent and descent in meters, counting only the edges whose both
+    /// ends have a known elevation.
+    pub fn climb(&self, graph: &RoadGraph) -> (f64, f64) {
+        self.path(graph)
+            .iter()
+            .tuple_windows()
+            .filter_map(|(&u, &v)| Some(graph.elevation(v)? - graph.elevation(u)?))
+            .fold((0.0, 0.0), |(ascent, descent), diff| {
+                (ascent + diff.max(0.0), descent + (-diff).max(0.0))
+            })
+    }
+
+    /// Elevation of every node with a known one, paired with its distance in
+    /// meters from the start along the route.
+    pub fn elevation_profile(&self, graph: &RoadGraph) -> Vec<(f64, f64)> {
+        let mut distance = 0.0;
+        let mut prev = None;
+        let mut profile = Vec::new();
+        for idx in self.path(graph) {
+            let Some(position) = graph.position(idx) else {
+                continue;
+            };
+            let here = point!(x: position.lon(), y: position.lat());
+            if let Some(prev) = prev {
+                distance += here.haversine_distance(&prev);
+            }
+            prev = Some(here);
+            if let Some(elevation) = graph.elevation(idx) {
+                profile.push((distance, elevation));
+            }
+        }
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use osmpbfreader::{Way, WayId};
+
+    use super::*;
+    use crate::{
+        graph::{AdjList, Nodes, Position},
+        Dijkstra, Extract, Profile, Router,
+    };
+
+    /// A way through nodes 1 to 4 going north then back south, on a DEM
+    /// rising 100 m per degree towards the north east, with node 4 off it.
+    /// The DEM is written to a directory named after `test`.
+    fn hilly_graph(test: &str) -> RoadGraph {
+        let dem_dir =
+            std::env::temp_dir().join(format!("read-osm-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dem_dir).unwrap();
+        let tile = [100i16, 200, 0, 100].map(i16::to_be_bytes).concat();
+        fs::write(dem_dir.join("N35E139.hgt"), tile).unwrap();
+        let position = |lat: f64| Position {
+            decimicro_lat: (lat * 1e7) as i32,
+            decimicro_lon: 1_391_000_000,
+        };
+        let extract = Extract {
+            header: RoadGraph::new(Nodes::new(), AdjList::new())
+                .header()
+                .clone(),
+            nodes: [(1, 35.1), (2, 35.3), (3, 35.2), (4, 36.2)]
+                .map(|(id, lat)| (NodeId(id), position(lat)))
+                .to_vec(),
+            ways: vec![Way {
+                id: WayId(10),
+                tags: [("highway".into(), "residential".into())]
+                    .into_iter()
+                    .collect(),
+                nodes: [1, 2, 3, 4].map(NodeId).to_vec(),
+            }],
+            relations: Vec::new(),
+        };
+        let mut profile = Profile::bicycle();
+        profile.min_component_size = 0;
+
+        let graph = RoadGraph::from_extract(&extract, &profile, Some(&dem_dir));
+        fs::remove_dir_all(&dem_dir).unwrap();
+        graph.unwrap()
+    }
+
+    #[test]
+    fn climb_counts_edges_with_known_elevations() {
+        let graph = hilly_graph("climb");
+        let route = Dijkstra.route(&graph, NodeId(1), NodeId(4)).unwrap();
+        assert_eq!(route.node_ids(&graph), [1, 2, 3, 4].map(NodeId));
+        let (ascent, descent) = route.climb(&graph);
+        assert!((ascent - 20.0).abs() < 1e-6, "{}", ascent);
+        assert!((descent - 10.0).abs() < 1e-6, "{}", descent);
+
+        let back = Dijkstra.route(&graph, NodeId(4), NodeId(1)).unwrap();
+        let (ascent, descent) = back.climb(&graph);
+        assert!((ascent - 10.0).abs() < 1e-6, "{}", ascent);
+        assert!((descent - 20.0).abs() < 1e-6, "{}", descent);
+    }
+
+    #[test]
+    fn elevation_profiles_follow_the_distance_along_the_route() {
+        let graph = hilly_graph("elevation-profile");
+        let route = Dijkstra.route(&graph, NodeId(1), NodeId(4)).unwrap();
+        let at = |lat: f64| point!(x: 139.1, y: lat);
+        let (d12, d23) = (
+            at(35.1).haversine_distance(&at(35.3)),
+            at(35.3).haversine_distance(&at(35.2)),
+        );
+
+        let profile = route.elevation_profile(&graph);
+        let expected = [(0.0, 20.0), (d12, 40.0), (d12 + d23, 30.0)];
+        assert_eq!(profile.len(), expected.len(), "{:?}", profile);
+        for ((distance, elevation), (expected_distance, expected_elevation)) in
+            profile.into_iter().zip(expected)
+        {
+            assert!((distance - expected_distance).abs() < 1e-6, "{}", distance);
+            assert!(
+                (elevation - expected_elevation).abs() < 1e-6,
+                "{}",
+                elevation
+            );
+        }
+    }
+}
@@ -0,0 +1,893 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use geo::{point, HaversineDistance};
+use osmpbfreader::NodeId;
+
+use crate::{
+    error::Error,
+    graph::{Edge, NodeIdx, RoadGraph},
+    progress::Settled,
+    route::Route,
+};
+
+/// A shortest path search over a [`RoadGraph`].
+pub trait Router {
+    /// Searches for a route between the nodes at `start` and `goal`,
+    /// returning `None` if `goal` can't be reached.
+    fn find(&self, graph: &RoadGraph, start: NodeIdx, goal: NodeIdx) -> Option<Route>;
+
+    /// Finds a route between the nodes with OSM IDs `start` and `goal`.
+    fn route(&self, graph: &RoadGraph, start: NodeId, goal: NodeId) -> Result<Route, Error> {
+        let index = |id| graph.index_of(id).ok_or(Error::NodeNotFound(id));
+        let (start_idx, goal_idx) = (index(start)?, index(goal)?);
+        self.find(graph, start_idx, goal_idx)
+            .ok_or(Error::Unreachable { start, goal })
+    }
+}
+
+/// A node together with its position in the graph's
+/// [`TurnRestrictions`](crate::restriction::TurnRestrictions), and the node
+/// it was reached from if the graph is [edge based](RoadGraph::is_edge_based).
+pub(crate) type State = (NodeIdx, u32, Option<NodeIdx>);
+
+/// States reachable from `state` in one step, with the edge taken and the
+/// cost of turning into it.
+pub(crate) fn successors<'a>(
+    graph: &'a RoadGraph,
+    state: State,
+) -> impl Iterator<Item = (State, &'a Edge, f64)> + 'a {
+    let (node, turn, prev) = state;
+    let edge_based = graph.is_edge_based();
+    graph.edges(node).iter().filter_map(move |edge| {
+        let next_turn = graph.turns().step(turn, node, edge.target)?;
+        let turn_cost = prev.map_or(0.0, |prev| graph.turn_cost(prev, node, edge));
+        Some((
+            (edge.target, next_turn, edge_based.then_some(node)),
+            edge,
+            turn_cost,
+        ))
+    })
+}
+
+/// Dijkstra search from the start node over the turn-expanded graph, where a
+/// node is reached once per turn restriction its path is in the middle of,
+/// and once per incoming edge when turns have costs.
+pub struct Dijkstra;
+
+impl Router for Dijkstra {
+    fn find(&self, graph: &RoadGraph, start: NodeIdx, goal: NodeIdx) -> Option<Route> {
+        search(graph, start, goal, |_| 0)
+    }
+}
+
+/// A* search, directed towards the goal by the great-circle distance to it at
+/// the [lowest cost per meter](RoadGraph::min_cost_per_meter) of the graph.
+pub struct AStar;
+
+impl Router for AStar {
+    fn find(&self, graph: &RoadGraph, start: NodeIdx, goal: NodeIdx) -> Option<Route> {
+        let Some(goal_position) = graph.position(goal) else {
+            return search(graph, start, goal, |_| 0);
+        };
+        let goal_point = point!(x: goal_position.lon(), y: goal_position.lat());
+        // Shrunk a little so that rounding of cached lengths and costs can't
+        // make the bound overestimate.
+        let cost_per_meter = graph.min_cost_per_meter() * 0.999;
+        search(graph, start, goal, |idx| {
+            graph.position(idx).map_or(0, |position| {
+                let distance =
+                    point!(x: position.lon(), y: position.lat()).haversine_distance(&goal_point);
+                to_fixed(distance * cost_per_meter)
+            })
+        })
+    }
+}
+
+/// Best first search ordered by the cost so far plus `heuristic`, which must
+/// never overestimate the remaining cost from a node to `goal`.
+fn search(
+    graph: &RoadGraph,
+    start: NodeIdx,
+    goal: NodeIdx,
+    heuristic: impl Fn(NodeIdx) -> u64,
+) -> Option<Route> {
+    // Costs are summed in thousandths so that the heap can order them.
+    let mut queue = BinaryHeap::new();
+    let mut dist = HashMap::<State, u64>::new();
+    let mut parent = HashMap::<State, (State, Edge, f64)>::new();
+    let mut settled = 0;
+    let progress = Settled::new();
+    queue.push(Reverse((heuristic(start), 0u64, (start, 0, None))));
+    dist.insert((start, 0, None), 0);
+    while let Some(Reverse((_, cur_dist, current))) = queue.pop() {
+        // A shorter path to `current` was found after this entry was pushed.
+        if cur_dist > dist[&current] {
+            continue;
+        }
+        settled += 1;
+        progress.update(settled);
+        if current.0 == goal {
+            let mut route = build_route((start, 0, None), current, &parent);
+            route.settled = settled;
+            return Some(route);
+        }
+
+        for (next, edge, turn_cost) in successors(graph, current) {
+            let next_dist = cur_dist + to_fixed(edge.cost + turn_cost);
+            if dist.get(&next).is_none_or(|&d| next_dist < d) {
+                dist.insert(next, next_dist);
+                parent.insert(next, (current, *edge, turn_cost));
+                queue.push(Reverse((
+                    next_dist + heuristic(edge.target),
+                    next_dist,
+                    next,
+                )));
+            }
+        }
+    }
+    None
+}
+
+/// Dijkstra searches from both the start and the goal, the latter along
+/// [incoming edges](RoadGraph::incoming_edges), until no path through the
+/// unsettled states can beat the best meeting of the two.
+///
+/// Graphs with turn costs or restrictions are searched by arcs, i.e. a node
+/// together with the node it's reached from. Restrictions with via ways can't
+/// be checked one turn at a time, so those graphs are left to [`Dijkstra`].
+pub struct BidirectionalDijkstra;
+
+/// A node and the node before it if searching by arcs.
+type Arc = (Option<NodeIdx>, NodeIdx);
+
+impl Router for BidirectionalDijkstra {
+    fn find(&self, graph: &RoadGraph, start: NodeIdx, goal: NodeIdx) -> Option<Route> {
+        let turns = graph.turns();
+        if turns.has_via_ways() || start == goal {
+            return Dijkstra.find(graph, start, goal);
+        }
+        let by_arcs = graph.is_edge_based() || !turns.is_empty();
+        // Cost of going from `from` through `via` along `edge`, or `None` if
+        // it is a forbidden turn.
+        let turn = |from: Option<NodeIdx>, via: NodeIdx, edge: &Edge| match from {
+            Some(from) if !turns.allows(from, via, edge.target) => None,
+            Some(from) => Some(graph.turn_cost(from, via, edge)),
+            None => Some(0.0),
+        };
+
+        // The forward search reaches arcs having passed them, while the
+        // backward one reaches arcs before leaving their node, so that the
+        // cost of a path through an arc is the sum of both distances.
+        let mut forward_queue = BinaryHeap::new();
+        let mut forward_dist = HashMap::<Arc, u64>::new();
+        let mut parent = HashMap::<Arc, (Arc, Edge, f64)>::new();
+        let mut backward_queue = BinaryHeap::new();
+        let mut backward_dist = HashMap::<Arc, u64>::new();
+        let mut child = HashMap::<Arc, (Arc, Edge, f64)>::new();
+        // The edge of each arc reached backwards.
+        let mut arc_edges = HashMap::<Arc, Edge>::new();
+
+        forward_queue.push(Reverse((0u64, (None, start))));
+        forward_dist.insert((None, start), 0);
+        if by_arcs {
+            for edge in graph.incoming_edges(goal) {
+                let arc = (Some(edge.target), goal);
+                backward_queue.push(Reverse((0u64, arc)));
+                backward_dist.insert(arc, 0);
+                arc_edges.insert(
+                    arc,
+                    Edge {
+                        target: goal,
+                        ..*edge
+                    },
+                );
+            }
+        } else {
+            backward_queue.push(Reverse((0u64, (None, goal))));
+            backward_dist.insert((None, goal), 0);
+        }
+
+        let mut best: Option<(u64, Arc)> = None;
+        let mut settled = 0;
+        let progress = Settled::new();
+        while let (Some(Reverse((forward_top, _))), Some(Reverse((backward_top, _)))) =
+            (forward_queue.peek(), backward_queue.peek())
+        {
+            if best.is_some_and(|(cost, _)| forward_top + backward_top >= cost) {
+                break;
+            }
+
+            if forward_top <= backward_top {
+                let Some(Reverse((cur_dist, current))) = forward_queue.pop() else {
+                    break;
+                };
+                if cur_dist > forward_dist[&current] {
+                    continue;
+                }
+                settled += 1;
+                progress.update(settled);
+                let (prev, node) = current;
+                for edge in graph.edges(node) {
+                    let Some(turn_cost) = turn(prev, node, edge) else {
+                        continue;
+                    };
+                    let next = (by_arcs.then_some(node), edge.target);
+                    let next_dist = cur_dist + to_fixed(edge.cost + turn_cost);
+                    if forward_dist.get(&next).is_none_or(|&d| next_dist < d) {
+                        forward_dist.insert(next, next_dist);
+                        parent.insert(next, (current, *edge, turn_cost));
+                        forward_queue.push(Reverse((next_dist, next)));
+                        if let Some(&d) = backward_dist.get(&next) {
+                            if best.is_none_or(|(cost, _)| next_dist + d < cost) {
+                                best = Some((next_dist + d, next));
+                            }
+                        }
+                    }
+                }
+            } else {
+                let Some(Reverse((cur_dist, current))) = backward_queue.pop() else {
+                    break;
+                };
+                if cur_dist > backward_dist[&current] {
+                    continue;
+                }
+                settled += 1;
+                progress.update(settled);
+                let (prev, node) = current;
+                // Arcs leading into `current`, with the edge and turn cost
+                // between them and `current`.
+                let steps = match prev {
+                    Some(prev) => {
+                        let arc_edge = arc_edges[&current];
+                        graph
+                            .incoming_edges(prev)
+                            .iter()
+                            .filter_map(|edge| {
+                                let turn_cost = turn(Some(edge.target), prev, &arc_edge)?;
+                                let arc = (Some(edge.target), prev);
+                                let edge = Edge {
+                                    target: prev,
+                                    ..*edge
+                                };
+                                Some((arc, edge, arc_edge, turn_cost))
+                            })
+                            .collect::<Vec<_>>()
+                    }
+                    None => graph
+                        .incoming_edges(node)
+                        .iter()
+                        .map(|edge| {
+                            let arc = (None, edge.target);
+                            let edge = Edge {
+                                target: node,
+                                ..*edge
+                            };
+                            (arc, edge, edge, 0.0)
+                        })
+                        .collect(),
+                };
+                for (next, next_edge, edge, turn_cost) in steps {
+                    let next_dist = cur_dist + to_fixed(edge.cost + turn_cost);
+                    if backward_dist.get(&next).is_none_or(|&d| next_dist < d) {
+                        backward_dist.insert(next, next_dist);
+                        child.insert(next, (current, edge, turn_cost));
+                        arc_edges.insert(next, next_edge);
+                        backward_queue.push(Reverse((next_dist, next)));
+                        if let Some(&d) = forward_dist.get(&next) {
+                            if best.is_none_or(|(cost, _)| next_dist + d < cost) {
+                                best = Some((next_dist + d, next));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let (_, meeting) = best?;
+        let mut route = Route {
+            distance: 0.0,
+            cost: 0.0,
+            nodes: Vec::new(),
+            settled,
+        };
+        let mut cur = meeting;
+        while let Some(&(prev, edge, turn_cost)) = parent.get(&cur) {
+            route.distance += edge.length;
+            route.cost += edge.cost + turn_cost;
+            route.nodes.push(cur.1);
+            cur = prev;
+        }
+        route.nodes.push(start);
+        route.nodes.reverse();
+        let mut cur = meeting;
+        while let Some(&(next, edge, turn_cost)) = child.get(&cur) {
+            route.distance += edge.length;
+            route.cost += edge.cost + turn_cost;
+            route.nodes.push(next.1);
+            cur = next;
+        }
+        Some(route)
+    }
+}
+
+pub(crate) fn to_fixed(cost: f64) -> u64 {
+    (cost * 1000.0).round() as u64
+}
+
+/// Follows `parent` back from `goal`, summing up the edges and turns on the way.
+fn build_route(start: State, goal: State, parent: &HashMap<State, (State, Edge, f64)>) -> Route {
+    let mut route = Route {
+        distance: 0.0,
+        cost: 0.0,
+        nodes: vec![goal.0],
+        settled: 0,
+    };
+    let mut cur = goal;
+    while cur != start {
+        let (prev, edge, turn_cost) = parent[&cur];
+        route.distance += edge.length;
+        route.cost += edge.cost + turn_cost;
+        route.nodes.push(prev.0);
+        cur = prev;
+    }
+    route.nodes.reverse();
+    route
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use osmpbfreader::{Node, Tags};
+
+    use crate::{
+        cch::CustomizableContractionHierarchy,
+        ch::ContractionHierarchy,
+        graph::{AdjList, Nodes},
+        profile::TurnCosts,
+        restriction::{RestrictionKind, TurnRestriction},
+    };
+
+    pub(crate) fn graph_from(edges: &[(i64, i64, f64)]) -> RoadGraph {
+        let mut adj_list = AdjList::new();
+        for &(u, v, len) in edges {
+            adj_list.entry(NodeId(u)).or_default().push(Edge {
+                target: NodeId(v),
+                length: len,
+                cost: len,
+                major: false,
+            });
+        }
+        RoadGraph::new(Nodes::new(), adj_list)
+    }
+
+    /// Route found by [`Dijkstra`], checking that the other routers agree.
+    fn route(graph: &RoadGraph, start: i64, goal: i64) -> Option<(f64, Vec<NodeId>)> {
+        let route = Dijkstra
+            .route(graph, NodeId(start), NodeId(goal))
+            .map(|route| (route.distance, route.node_ids(graph)))
+            .ok();
+        let ch = ContractionHierarchy::build(graph);
+        let cch = CustomizableContractionHierarchy::build(graph)
+            .customize(graph)
+            .unwrap();
+        for router in [&AStar as &dyn Router, &BidirectionalDijkstra, &ch, &cch] {
+            assert_eq!(
+                router
+                    .route(graph, NodeId(start), NodeId(goal))
+                    .map(|route| (route.distance, route.node_ids(graph)))
+                    .ok(),
+                route
+            );
+        }
+        route
+    }
+
+    #[test]
+    fn dijkstra_updates_parent_when_a_shorter_path_is_found() {
+        // 1 reaches 2 through a long direct edge first, but 1 -> 3 -> 2 is shorter.
+        let graph = graph_from(&[(1, 2, 10.0), (1, 3, 1.0), (3, 2, 1.0), (2, 4, 1.0)]);
+
+        assert_eq!(
+            route(&graph, 1, 4),
+            Some((3.0, [1, 3, 2, 4].map(NodeId).to_vec()))
+        );
+    }
+
+    #[test]
+    fn dijkstra_skips_stale_heap_entries() {
+        // 4 is pushed three times with decreasing distances before it is settled.
+        let graph = graph_from(&[
+            (1, 2, 1.0),
+            (1, 3, 2.0),
+            (1, 5, 3.0),
+            (1, 4, 9.0),
+            (2, 4, 7.0),
+            (3, 4, 4.0),
+            (5, 4, 0.5),
+            (4, 6, 1.0),
+        ]);
+
+        assert_eq!(
+            route(&graph, 1, 6),
+            Some((4.5, [1, 5, 4, 6].map(NodeId).to_vec()))
+        );
+    }
+
+    #[test]
+    fn dijkstra_follows_edge_directions() {
+        let graph = graph_from(&[(1, 2, 1.0), (3, 2, 1.0), (1, 4, 5.0), (4, 3, 5.0)]);
+
+        assert_eq!(
+            route(&graph, 1, 3),
+            Some((10.0, [1, 4, 3].map(NodeId).to_vec()))
+        );
+        assert_eq!(route(&graph, 3, 1), None);
+    }
+
+    #[test]
+    fn routers_tell_unknown_nodes_from_unreachable_ones() {
+        let graph = graph_from(&[(1, 2, 1.0), (3, 2, 1.0)]);
+
+        for router in [&Dijkstra as &dyn Router, &AStar, &BidirectionalDijkstra] {
+            assert!(matches!(
+                router.route(&graph, NodeId(1), NodeId(4)),
+                Err(Error::NodeNotFound(NodeId(4)))
+            ));
+            assert!(matches!(
+                router.route(&graph, NodeId(1), NodeId(3)),
+                Err(Error::Unreachable {
+                    start: NodeId(1),
+                    goal: NodeId(3)
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn dijkstra_start_equals_goal() {
+        let graph = graph_from(&[(1, 2, 1.0)]);
+
+        assert_eq!(route(&graph, 1, 1), Some((0.0, vec![NodeId(1)])));
+    }
+
+    fn restricted(graph: RoadGraph, kind: RestrictionKind, nodes: &[i64]) -> RoadGraph {
+        graph.with_restrictions(vec![TurnRestriction {
+            kind,
+            nodes: nodes.iter().copied().map(NodeId).collect(),
+        }])
+    }
+
+    #[test]
+    fn dijkstra_avoids_forbidden_turns() {
+        let graph = graph_from(&[(1, 2, 1.0), (2, 3, 1.0), (2, 4, 1.0), (4, 3, 1.0)]);
+        let graph = restricted(graph, RestrictionKind::No, &[1, 2, 3]);
+
+        assert_eq!(
+            route(&graph, 1, 3),
+            Some((3.0, [1, 2, 4, 3].map(NodeId).to_vec()))
+        );
+        assert_eq!(
+            route(&graph, 2, 3),
+            Some((1.0, [2, 3].map(NodeId).to_vec()))
+        );
+    }
+
+    #[test]
+    fn dijkstra_takes_mandatory_turns() {
+        let graph = graph_from(&[
+            (1, 2, 1.0),
+            (2, 3, 1.0),
+            (2, 4, 1.0),
+            (4, 3, 1.0),
+            (2, 5, 1.0),
+        ]);
+        let graph = restricted(graph, RestrictionKind::Only, &[1, 2, 4]);
+
+        assert_eq!(
+            route(&graph, 1, 3),
+            Some((3.0, [1, 2, 4, 3].map(NodeId).to_vec()))
+        );
+        assert_eq!(route(&graph, 1, 5), None);
+    }
+
+    #[test]
+    fn dijkstra_avoids_forbidden_via_way_manoeuvres() {
+        let graph = graph_from(&[
+            (1, 2, 1.0),
+            (2, 3, 1.0),
+            (3, 4, 1.0),
+            (3, 5, 1.0),
+            (5, 4, 1.0),
+            (6, 2, 1.0),
+        ]);
+        let graph = restricted(graph, RestrictionKind::No, &[1, 2, 3, 4]);
+
+        assert_eq!(
+            route(&graph, 1, 4),
+            Some((4.0, [1, 2, 3, 5, 4].map(NodeId).to_vec()))
+        );
+        assert_eq!(
+            route(&graph, 6, 4),
+            Some((3.0, [6, 2, 3, 4].map(NodeId).to_vec()))
+        );
+    }
+
+    #[test]
+    fn dijkstra_follows_restrictions_starting_inside_others() {
+        let graph = graph_from(&[
+            (1, 2, 1.0),
+            (2, 3, 1.0),
+            (3, 4, 1.0),
+            (3, 5, 1.0),
+            (3, 6, 1.0),
+            (6, 4, 1.0),
+            (6, 5, 1.0),
+        ]);
+        let no = |nodes: &[i64]| TurnRestriction {
+            kind: RestrictionKind::No,
+            nodes: nodes.iter().copied().map(NodeId).collect(),
+        };
+        // 2, 3, 5 starts while 1, 2, 3, 4 is being followed.
+        let graph = graph.with_restrictions(vec![no(&[1, 2, 3, 4]), no(&[2, 3, 5])]);
+
+        assert_eq!(
+            route(&graph, 1, 5),
+            Some((4.0, [1, 2, 3, 6, 5].map(NodeId).to_vec()))
+        );
+        assert_eq!(
+            route(&graph, 1, 4),
+            Some((4.0, [1, 2, 3, 6, 4].map(NodeId).to_vec()))
+        );
+        assert_eq!(
+            route(&graph, 2, 4),
+            Some((2.0, [2, 3, 4].map(NodeId).to_vec()))
+        );
+
+        let only = TurnRestriction {
+            kind: RestrictionKind::Only,
+            nodes: [2, 3, 6].map(NodeId).to_vec(),
+        };
+        let graph = graph.with_restrictions(vec![no(&[1, 2, 3, 4]), only]);
+        assert_eq!(
+            route(&graph, 1, 5),
+            Some((4.0, [1, 2, 3, 6, 5].map(NodeId).to_vec()))
+        );
+    }
+
+    /// A graph with nodes at `(id, lat, lon)` and bidirectional edges
+    /// `(u, v, cost, major)`, routed with turn costs.
+    fn turning_graph(nodes: &[(i64, f64, f64)], edges: &[(i64, i64, f64, bool)]) -> RoadGraph {
+        let mut adj_list = AdjList::new();
+        for &(u, v, cost, major) in edges {
+            for (from, to) in [(u, v), (v, u)] {
+                adj_list.entry(NodeId(from)).or_default().push(Edge {
+                    target: NodeId(to),
+                    length: cost,
+                    cost,
+                    major,
+                });
+            }
+        }
+        RoadGraph::new(nodes_at(nodes), adj_list).with_turn_costs(Some(turn_costs()))
+    }
+
+    /// Nodes at `(id, lat, lon)`.
+    fn nodes_at(nodes: &[(i64, f64, f64)]) -> Nodes {
+        nodes
+            .iter()
+            .map(|&(id, lat, lon)| {
+                let node = Node {
+                    id: NodeId(id),
+                    tags: Tags::new(),
+                    decimicro_lat: (lat * 1e7) as i32,
+                    decimicro_lon: (lon * 1e7) as i32,
+                };
+                (node.id, node)
+            })
+            .collect()
+    }
+
+    pub(crate) fn turn_costs() -> TurnCosts {
+        TurnCosts {
+            u_turn: 20.0,
+            sharp_turn: 5.0,
+            sharp_angle: 130.0,
+            major_crossing: 3.0,
+            major_highways: Default::default(),
+        }
+    }
+
+    #[test]
+    fn dijkstra_avoids_sharp_turns() {
+        // Turning from 1 -> 2 straight to 3 is a 148 degree turn, going through
+        // 4 takes two turns of about 100 and 120 degrees.
+        let graph = turning_graph(
+            &[(1, 0.0, 0.0), (2, 0.0, 1.0), (3, 0.5, 0.2), (4, 1.0, 0.8)],
+            &[
+                (1, 2, 1.0, false),
+                (2, 3, 1.0, false),
+                (2, 4, 0.5, false),
+                (4, 3, 0.5, false),
+            ],
+        );
+
+        for router in [&Dijkstra as &dyn Router, &BidirectionalDijkstra] {
+            let route = router.route(&graph, NodeId(1), NodeId(3)).unwrap();
+            assert_eq!(route.node_ids(&graph), [1, 2, 4, 3].map(NodeId));
+            assert_eq!(route.cost, 2.0);
+        }
+    }
+
+    #[test]
+    fn dijkstra_penalizes_crossing_major_roads() {
+        // 1 - 2 - 3 crosses the major road 4 - 2 - 5.
+        let graph = turning_graph(
+            &[
+                (1, 0.0, -1.0),
+                (2, 0.0, 0.0),
+                (3, 0.0, 1.0),
+                (4, 1.0, 0.0),
+                (5, -1.0, 0.0),
+            ],
+            &[
+                (1, 2, 1.0, false),
+                (2, 3, 1.0, false),
+                (4, 2, 1.0, true),
+                (2, 5, 1.0, true),
+            ],
+        );
+
+        let cost = |start, goal| {
+            let cost = Dijkstra
+                .route(&graph, NodeId(start), NodeId(goal))
+                .unwrap()
+                .cost;
+            let bidirectional = BidirectionalDijkstra
+                .route(&graph, NodeId(start), NodeId(goal))
+                .unwrap();
+            assert_eq!(bidirectional.cost, cost);
+            cost
+        };
+        assert_eq!(cost(1, 3), 5.0);
+        assert_eq!(cost(1, 4), 2.0);
+        assert_eq!(cost(4, 5), 2.0);
+    }
+
+    #[test]
+    fn dijkstra_penalizes_u_turns() {
+        // Going 1 -> 2 -> 3 is forbidden, but 1 -> 2 -> 4 -> 2 -> 3 turns
+        // around at 4 and is shorter than 1 -> 5 -> 3.
+        let graph = turning_graph(
+            &[
+                (1, 0.0, 0.0),
+                (2, 0.0, 1.0),
+                (3, 0.0, 2.0),
+                (4, 1.0, 1.0),
+                (5, -1.0, 1.0),
+            ],
+            &[
+                (1, 2, 1.0, false),
+                (2, 3, 1.0, false),
+                (2, 4, 0.5, false),
+                (1, 5, 5.0, false),
+                (5, 3, 5.0, false),
+            ],
+        );
+        let graph = restricted(graph, RestrictionKind::No, &[1, 2, 3]);
+
+        for router in [&Dijkstra as &dyn Router, &BidirectionalDijkstra] {
+            let route = router.route(&graph, NodeId(1), NodeId(3)).unwrap();
+            assert_eq!(route.node_ids(&graph), [1, 5, 3].map(NodeId));
+        }
+
+        let graph = graph.with_turn_costs(Some(TurnCosts {
+            u_turn: 0.0,
+            ..turn_costs()
+        }));
+        for router in [&Dijkstra as &dyn Router, &BidirectionalDijkstra] {
+            let route = router.route(&graph, NodeId(1), NodeId(3)).unwrap();
+            assert_eq!(route.node_ids(&graph), [1, 2, 4, 2, 3].map(NodeId));
+        }
+    }
+
+    /// A 10 x 10 grid of 100 m blocks with costs of one unit per meter.
+    pub(crate) fn grid_graph() -> RoadGraph {
+        grid_graph_with_costs(|_, _| 100.0)
+    }
+
+    pub(crate) fn grid_graph_with_costs(cost: fn(i64, i64) -> f64) -> RoadGraph {
+        let id = |row: i64, col: i64| row * 10 + col;
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        for row in 0..10 {
+            for col in 0..10 {
+                let (lat, lon) = (35.0 + row as f64 * 0.0009, 139.0 + col as f64 * 0.0011);
+                nodes.push((id(row, col), lat, lon));
+                if row < 9 {
+                    let (u, v) = (id(row, col), id(row + 1, col));
+                    edges.push((u, v, cost(u, v), false));
+                }
+                if col < 9 {
+                    let (u, v) = (id(row, col), id(row, col + 1));
+                    edges.push((u, v, cost(u, v), false));
+                }
+            }
+        }
+        turning_graph(&nodes, &edges)
+            .with_turn_costs(None)
+            .with_min_cost_per_meter(0.9)
+    }
+
+    #[test]
+    fn astar_finds_dijkstra_routes_settling_fewer_states() {
+        let graph = grid_graph();
+
+        for (start, goal) in [(0, 99), (45, 47), (90, 9), (33, 33)] {
+            let dijkstra = Dijkstra.route(&graph, NodeId(start), NodeId(goal)).unwrap();
+            let astar = AStar.route(&graph, NodeId(start), NodeId(goal)).unwrap();
+            assert_eq!(astar.cost, dijkstra.cost, "{} -> {}", start, goal);
+            assert!(astar.settled <= dijkstra.settled, "{} -> {}", start, goal);
+        }
+        let settled = |router: &dyn Router| {
+            router
+                .route(&graph, NodeId(45), NodeId(47))
+                .unwrap()
+                .settled
+        };
+        assert!(settled(&AStar) < settled(&Dijkstra));
+    }
+
+    #[test]
+    fn bidirectional_dijkstra_finds_dijkstra_routes() {
+        for graph in [
+            grid_graph(),
+            grid_graph().with_turn_costs(Some(turn_costs())),
+        ] {
+            for (start, goal) in [(0, 99), (45, 47), (90, 9), (33, 33), (0, 1)] {
+                let dijkstra = Dijkstra.route(&graph, NodeId(start), NodeId(goal)).unwrap();
+                let bidirectional = BidirectionalDijkstra
+                    .route(&graph, NodeId(start), NodeId(goal))
+                    .unwrap();
+                assert_eq!(bidirectional.cost, dijkstra.cost, "{} -> {}", start, goal);
+                let ids = bidirectional.node_ids(&graph);
+                assert_eq!(ids.first(), Some(&NodeId(start)));
+                assert_eq!(ids.last(), Some(&NodeId(goal)));
+            }
+        }
+        let settled = |router: &dyn Router| {
+            router
+                .route(&grid_graph(), NodeId(0), NodeId(99))
+                .unwrap()
+                .settled
+        };
+        assert!(settled(&BidirectionalDijkstra) < settled(&Dijkstra));
+    }
+
+    /// Junctions 1 to 4 at the corners of a square, joined by chains of
+    /// unit cost segments: two parallel ones from 1 to 2, a hairpin from 2 to
+    /// 4 and a oneway from 3 to 4, at `(x, y)` in thousandths of a degree.
+    fn chain_graph() -> RoadGraph {
+        let nodes = [
+            (1, 0.0, 0.0),
+            (2, 3.0, 0.0),
+            (3, 0.0, 3.0),
+            (4, 3.0, 3.0),
+            (11, 1.0, 0.0),
+            (12, 2.0, 0.0),
+            (13, 0.0, 1.5),
+            (14, 3.0, 4.0),
+            (15, 2.5, 3.5),
+            (16, 1.0, 3.0),
+            (17, 2.0, 3.0),
+            (19, 1.0, -1.0),
+            (20, 2.0, -1.5),
+            (21, 3.0, -1.0),
+        ]
+        .map(|(id, x, y)| (id, 35.0 + y * 0.001, 139.0 + x * 0.001));
+        let chains: [(&[i64], bool); 5] = [
+            (&[1, 11, 12, 2], true),
+            (&[1, 19, 20, 21, 2], true),
+            (&[1, 13, 3], true),
+            (&[2, 14, 15, 4], true),
+            (&[3, 16, 17, 4], false),
+        ];
+        let mut adj_list = AdjList::new();
+        for (path, both_ways) in chains {
+            for (&u, &v) in path.iter().tuple_windows() {
+                let ends = if both_ways {
+                    vec![(u, v), (v, u)]
+                } else {
+                    vec![(u, v)]
+                };
+                for (from, to) in ends {
+                    adj_list.entry(NodeId(from)).or_default().push(Edge {
+                        target: NodeId(to),
+                        length: 1.0,
+                        cost: 1.0,
+                        major: false,
+                    });
+                }
+            }
+        }
+        RoadGraph::new(nodes_at(&nodes), adj_list)
+    }
+
+    #[test]
+    fn collapsed_chains_keep_routes() {
+        for turn_costs in [None, Some(turn_costs())] {
+            let graph = chain_graph().with_turn_costs(turn_costs.clone());
+            let collapsed = chain_graph().with_turn_costs(turn_costs).collapse_chains();
+            assert!(collapsed.edge_count() < graph.edge_count());
+            // The parallel chains from 1 to 2 each keep a node.
+            for (id, junction) in [
+                (11, true),
+                (12, false),
+                (19, true),
+                (20, false),
+                (16, false),
+            ] {
+                let idx = collapsed.index_of(NodeId(id)).unwrap();
+                assert_eq!(collapsed.is_junction(idx), junction, "{}", id);
+            }
+
+            let ch = ContractionHierarchy::build(&collapsed);
+            let cch = CustomizableContractionHierarchy::build(&collapsed)
+                .customize(&collapsed)
+                .unwrap();
+            for (start, goal) in [(1, 4), (4, 1), (3, 2), (2, 3), (11, 4), (4, 19)] {
+                let expected = Dijkstra.route(&graph, NodeId(start), NodeId(goal)).unwrap();
+                for router in [
+                    &Dijkstra as &dyn Router,
+                    &AStar,
+                    &BidirectionalDijkstra,
+                    &ch,
+                    &cch,
+                ] {
+                    let route = router
+                        .route(&collapsed, NodeId(start), NodeId(goal))
+                        .unwrap();
+                    assert_eq!(route.cost, expected.cost, "{} -> {}", start, goal);
+                    assert_eq!(route.distance, expected.distance, "{} -> {}", start, goal);
+                    assert_eq!(
+                        route.node_ids(&collapsed),
+                        expected.node_ids(&graph),
+                        "{} -> {}",
+                        start,
+                        goal
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_chains_route_from_collapsed_nodes() {
+        let graph = chain_graph().with_turn_costs(Some(turn_costs()));
+        let mut collapsed = chain_graph()
+            .with_turn_costs(Some(turn_costs()))
+            .collapse_chains();
+        for id in [16, 20, 14] {
+            let idx = collapsed.index_of(NodeId(id)).unwrap();
+            collapsed.split_chains_at(idx);
+        }
+
+        for (start, goal) in [(16, 20), (20, 16), (14, 1), (1, 14)] {
+            let expected = Dijkstra.route(&graph, NodeId(start), NodeId(goal)).unwrap();
+            let route = Dijkstra
+                .route(&collapsed, NodeId(start), NodeId(goal))
+                .unwrap();
+            assert_eq!(route.cost, expected.cost, "{} -> {}", start, goal);
+            assert_eq!(
+                route.node_ids(&collapsed),
+                expected.node_ids(&graph),
+                "{} -> {}",
+                start,
+                goal
+            );
+        }
+    }
+}
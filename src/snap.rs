@@ -0,0 +1,108 @@
+use rstar::{primitives::GeomWithData, RTree};
+
+use crate::graph::{NodeIdx, RoadGraph};
+
+/// Spatial index over the graph nodes for snapping coordinates.
+///
+/// Points are stored on the unit sphere so that the euclidean nearest neighbor
+/// is also the nearest one along the earth's surface.
+pub struct NodeIndex(RTree<GeomWithData<[f64; 3], NodeIdx>>);
+
+impl NodeIndex {
+    pub fn new(graph: &RoadGraph) -> Self {
+        Self::filtered(graph, |_| true)
+    }
+
+    /// Index over the nodes of the graph for which `keep` holds.
+    pub fn filtered(graph: &RoadGraph, keep: impl Fn(NodeIdx) -> bool) -> Self {
+        NodeIndex(RTree::bulk_load(
+            graph
+                .positions()
+                .filter(|&(idx, _)| keep(idx))
+                .map(|(idx, p)| GeomWithData::new(to_unit_sphere(p.lat(), p.lon()), idx))
+                .collect(),
+        ))
+    }
+
+    pub fn nearest(&self, lat: f64, lon: f64) -> Option<NodeIdx> {
+        self.0
+            .nearest_neighbor(&to_unit_sphere(lat, lon))
+            .map(|entry| entry.data)
+    }
+}
+
+fn to_unit_sphere(lat: f64, lon: f64) -> [f64; 3] {
+    let (lat, lon) = (lat.to_radians(), lon.to_radians());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{Node, NodeId, Tags};
+
+    use super::*;
+    use crate::graph::{AdjList, Edge, Nodes};
+
+    fn node(id: i64, lat: f64, lon: f64) -> (NodeId, Node) {
+        let node = Node {
+            id: NodeId(id),
+            tags: Tags::new(),
+            decimicro_lat: (lat * 1e7) as i32,
+            decimicro_lon: (lon * 1e7) as i32,
+        };
+        (node.id, node)
+    }
+
+    /// Nodes 1 and 2 in Tokyo, 3 just east of the antimeridian and 4 only
+    /// known as the end of an edge.
+    fn graph() -> RoadGraph {
+        let nodes = [
+            node(1, 35.68, 139.76),
+            node(2, 35.69, 139.77),
+            node(3, 0.0, -179.99),
+        ]
+        .into_iter()
+        .collect::<Nodes>();
+        let edge = Edge {
+            target: NodeId(4),
+            length: 1.0,
+            cost: 1.0,
+            major: false,
+        };
+        let adj_list = [(NodeId(1), vec![edge])].into_iter().collect::<AdjList>();
+        RoadGraph::new(nodes, adj_list)
+    }
+
+    fn nearest_id(graph: &RoadGraph, index: &NodeIndex, lat: f64, lon: f64) -> Option<i64> {
+        index.nearest(lat, lon).map(|idx| graph.id_of(idx).0)
+    }
+
+    #[test]
+    fn coordinates_snap_to_the_nearest_node() {
+        let graph = graph();
+        let index = NodeIndex::new(&graph);
+        assert_eq!(nearest_id(&graph, &index, 35.681, 139.761), Some(1));
+        assert_eq!(nearest_id(&graph, &index, 35.688, 139.768), Some(2));
+        // Across the antimeridian rather than around the globe.
+        assert_eq!(nearest_id(&graph, &index, 0.0, 179.99), Some(3));
+    }
+
+    #[test]
+    fn coordinates_outside_the_graph_snap_to_its_edge() {
+        let graph = graph();
+        let index = NodeIndex::filtered(&graph, |idx| graph.id_of(idx) != NodeId(3));
+        assert_eq!(nearest_id(&graph, &index, 50.0, 150.0), Some(2));
+        assert_eq!(nearest_id(&graph, &index, -90.0, 0.0), Some(1));
+    }
+
+    #[test]
+    fn empty_graphs_have_no_nearest_node() {
+        let empty = RoadGraph::new(Nodes::new(), AdjList::new());
+        assert_eq!(NodeIndex::new(&empty).nearest(35.68, 139.76), None);
+
+        // Nodes without a position are never snapped to.
+        let graph = graph();
+        let index = NodeIndex::filtered(&graph, |idx| graph.id_of(idx) == NodeId(4));
+        assert_eq!(index.nearest(35.68, 139.76), None);
+    }
+}